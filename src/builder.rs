@@ -0,0 +1,109 @@
+// Opens a Connection with options applied before it is handed out.
+use super::*;
+use std::path::PathBuf;
+
+// Which library a Connection talks to spacenavd through. Every variant exists
+// whatever features are enabled; picking one that wasn't compiled in fails
+// with Error::Unsupported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum BackendKind {
+    // The pure Rust client, over the daemon's AF_UNIX socket
+    Native,
+    // The shared global libspnav connection
+    Libspnav,
+    // Magellan events from the X server
+    X11,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionBuilder {
+    backend: Option<BackendKind>,
+    socket: Option<PathBuf>,
+    #[cfg_attr(not(feature = "native"), allow(dead_code))]
+    timeout: Option<Duration>,
+    event_mask: Option<Vec<EventType>>,
+    client_name: Option<String>,
+}
+
+impl ConnectionBuilder {
+    pub fn new() -> Self {
+        ConnectionBuilder::default()
+    }
+    // Defaults to native when that feature is enabled, then libspnav, then X11
+    pub fn backend(mut self, backend: BackendKind) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+    // Overrides native::socket_path's discovery through $SPNAV_SOCKET and
+    // $XDG_RUNTIME_DIR. libspnav can't be pointed elsewhere and X11 doesn't
+    // use a socket of spacenavd's, so they refuse to connect when this is set.
+    pub fn socket_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.socket = Some(path.into());
+        self
+    }
+    // Longest the native backend waits for the daemon to accept the connection
+    // and answer the handshake
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    // See Connection::set_event_mask
+    pub fn event_mask(mut self, types: &[EventType]) -> Self {
+        self.event_mask = Some(types.to_vec());
+        self
+    }
+    // Name to register with the daemon. Daemons too old to take it still
+    // accept the connection; check Connection::protocol_version to tell.
+    pub fn client_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.client_name = Some(name.into());
+        self
+    }
+    pub fn connect(self) -> Result<Connection> {
+        let conn = self.open()?;
+        if let Some(types) = &self.event_mask {
+            conn.set_event_mask(types)?;
+        }
+        if let Some(name) = &self.client_name {
+            match conn.set_client_name(name) {
+                Ok(()) | Err(Error::Unsupported { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(conn)
+    }
+    // Opts into reconnecting with backoff whenever the daemon goes away; see
+    // ReconnectingConnection
+    pub fn connect_reconnecting(self) -> Result<ReconnectingConnection> {
+        ReconnectingConnection::new(self)
+    }
+
+    fn open(&self) -> Result<Connection> {
+        let backend = self.backend.unwrap_or(if cfg!(feature = "native") {
+            BackendKind::Native
+        } else if cfg!(feature = "libspnav") {
+            BackendKind::Libspnav
+        } else {
+            BackendKind::X11
+        });
+        match backend {
+            #[cfg(feature = "native")]
+            BackendKind::Native => {
+                let path = self.socket.clone().unwrap_or_else(native::socket_path);
+                let client = match self.timeout {
+                    Some(timeout) => native::Client::connect_timeout(path, timeout)?,
+                    None => native::Client::connect(path)?,
+                };
+                client.try_into()
+            }
+            #[cfg(feature = "libspnav")]
+            BackendKind::Libspnav if self.socket.is_none() => Connection::libspnav(),
+            // The X server comes from $DISPLAY, not a spacenavd socket
+            #[cfg(feature = "x11")]
+            BackendKind::X11 if self.socket.is_none() => Connection::x11(),
+            _ => Err(Error::Unsupported {
+                op: Operation::Open,
+            }),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+// Wakes up a blocked Connection::wait_cancellable from another thread. A
+// connected socket pair serves as the self-pipe, polled next to the daemon fd.
+use super::*;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Cheap to clone; every clone cancels the same waits. Cancellation sticks until
+// `reset` so a wait that starts after `cancel` returns straight away too.
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    cancelled: AtomicBool,
+    // Held across setting or clearing the flag and the matching write or
+    // drain, so there is a byte in the pipe exactly while the flag is set
+    lock: Mutex<()>,
+    rx: UnixStream,
+    tx: UnixStream,
+}
+
+impl CancelHandle {
+    pub fn new() -> Result<CancelHandle> {
+        let (rx, tx) = UnixStream::pair()
+            .and_then(|(rx, tx)| {
+                rx.set_nonblocking(true)?;
+                tx.set_nonblocking(true)?;
+                Ok((rx, tx))
+            })
+            .map_err(|e| Error::from_io(Operation::Open, &e))?;
+        Ok(CancelHandle {
+            inner: Arc::new(Inner {
+                cancelled: AtomicBool::new(false),
+                lock: Mutex::new(()),
+                rx,
+                tx,
+            }),
+        })
+    }
+    pub fn cancel(&self) {
+        let _guard = self.inner.lock();
+        if !self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            // A full buffer already wakes the poller, so errors don't matter
+            let _ = (&self.inner.tx).write(&[1]);
+        }
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+    // Re-arms the handle so later waits block again
+    pub fn reset(&self) {
+        let _guard = self.inner.lock();
+        self.inner.cancelled.store(false, Ordering::SeqCst);
+        let mut buf = [0u8; 64];
+        while let Ok(n) = (&self.inner.rx).read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+
+    pub(crate) fn fd(&self) -> RawFd {
+        self.inner.rx.as_raw_fd()
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
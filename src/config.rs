@@ -0,0 +1,92 @@
+// Typed access to spacenavd's configuration through the protocol v1 config
+// requests. Changes take effect for every client right away but only survive a
+// daemon restart once `save` is called.
+use super::*;
+
+use crate::proto::{
+    REQ_CFG_SAVE, REQ_GCFG_AXISMAP, REQ_GCFG_DEADZONE, REQ_GCFG_INVERT, REQ_GCFG_SENS,
+    REQ_GCFG_SENS_AXIS, REQ_GCFG_SWAPYZ, REQ_SCFG_AXISMAP, REQ_SCFG_DEADZONE, REQ_SCFG_INVERT,
+    REQ_SCFG_SENS, REQ_SCFG_SENS_AXIS, REQ_SCFG_SWAPYZ,
+};
+
+// Per-axis settings are in x, y, z, rx, ry, rz order
+#[derive(Debug, Clone, Copy)]
+pub struct DaemonConfig<'a> {
+    conn: &'a Connection,
+}
+
+const OP: Operation = Operation::Config;
+
+impl<'a> DaemonConfig<'a> {
+    pub(crate) fn new(conn: &'a Connection) -> Self {
+        DaemonConfig { conn }
+    }
+
+    // Global sensitivity multiplier
+    pub fn sensitivity(&self) -> Result<f32> {
+        Ok(f32::from_bits(self.get(REQ_GCFG_SENS, [0; 7])?[1] as u32))
+    }
+    pub fn set_sensitivity(&self, sens: f32) -> Result<()> {
+        self.set(REQ_SCFG_SENS, [sens.to_bits() as i32, 0, 0, 0, 0, 0, 0])
+    }
+
+    pub fn axis_sensitivity(&self) -> Result<[f32; 6]> {
+        let r = self.get(REQ_GCFG_SENS_AXIS, [0; 7])?;
+        Ok(std::array::from_fn(|i| f32::from_bits(r[1 + i] as u32)))
+    }
+    pub fn set_axis_sensitivity(&self, sens: [f32; 6]) -> Result<()> {
+        let mut data = [0; 7];
+        for (d, s) in data.iter_mut().zip(sens) {
+            *d = s.to_bits() as i32;
+        }
+        self.set(REQ_SCFG_SENS_AXIS, data)
+    }
+
+    // Deadzones are per device axis, before axis mapping is applied
+    pub fn deadzone(&self, dev_axis: i32) -> Result<i32> {
+        Ok(self.get(REQ_GCFG_DEADZONE, [dev_axis, 0, 0, 0, 0, 0, 0])?[2])
+    }
+    pub fn set_deadzone(&self, dev_axis: i32, deadzone: i32) -> Result<()> {
+        self.set(REQ_SCFG_DEADZONE, [dev_axis, deadzone, 0, 0, 0, 0, 0])
+    }
+
+    pub fn inverted(&self) -> Result<[bool; 6]> {
+        let r = self.get(REQ_GCFG_INVERT, [0; 7])?;
+        Ok(std::array::from_fn(|i| r[1 + i] != 0))
+    }
+    pub fn set_inverted(&self, invert: [bool; 6]) -> Result<()> {
+        let mut data = [0; 7];
+        for (d, inv) in data.iter_mut().zip(invert) {
+            *d = inv as i32;
+        }
+        self.set(REQ_SCFG_INVERT, data)
+    }
+
+    // Which output axis (0-5) a device axis drives, or -1 if it's ignored
+    pub fn axis_map(&self, dev_axis: i32) -> Result<i32> {
+        Ok(self.get(REQ_GCFG_AXISMAP, [dev_axis, 0, 0, 0, 0, 0, 0])?[2])
+    }
+    pub fn set_axis_map(&self, dev_axis: i32, axis: i32) -> Result<()> {
+        self.set(REQ_SCFG_AXISMAP, [dev_axis, axis, 0, 0, 0, 0, 0])
+    }
+
+    // Whether the Y and Z axes are swapped
+    pub fn swap_yz(&self) -> Result<bool> {
+        Ok(self.get(REQ_GCFG_SWAPYZ, [0; 7])?[1] != 0)
+    }
+    pub fn set_swap_yz(&self, swap: bool) -> Result<()> {
+        self.set(REQ_SCFG_SWAPYZ, [swap as i32, 0, 0, 0, 0, 0, 0])
+    }
+
+    // Writes the current settings to the daemon's config file
+    pub fn save(&self) -> Result<()> {
+        self.set(REQ_CFG_SAVE, [0; 7])
+    }
+
+    fn get(&self, req: i32, data: [i32; 7]) -> Result<[i32; 8]> {
+        self.conn.request(OP, req, data)
+    }
+    fn set(&self, req: i32, data: [i32; 7]) -> Result<()> {
+        self.conn.request(OP, req, data).map(|_| ())
+    }
+}
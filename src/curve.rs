@@ -0,0 +1,101 @@
+// Response curves, for finer control near the center without giving up speed
+// at full deflection. Each axis is normalized to [-1, 1] by the full-scale
+// value, bent by its curve and scaled back. Curves are symmetric: they shape
+// the magnitude and keep the sign.
+use super::*;
+use crate::filter::{axes, with_axes};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Curve {
+    #[default]
+    Linear,
+    // (e^(k|v|) - 1) / (e^k - 1): flat near the center for k > 0, steeper
+    // there for k < 0, linear at 0
+    Exponential(f64),
+    // |v|^p: p > 1 gives more precision near the center
+    Power(f64),
+    // Straight lines through (input, output) points on [0, 1], starting from
+    // (0, 0). Past the last point the output stays at its value.
+    Piecewise(Vec<(f64, f64)>),
+}
+
+impl Curve {
+    // Maps a value in [-1, 1]; anything outside is clamped first
+    pub fn apply(&self, v: f64) -> f64 {
+        let x = v.abs().min(1.0);
+        let y = match self {
+            Curve::Linear => x,
+            Curve::Exponential(k) if *k == 0.0 => x,
+            Curve::Exponential(k) => (k * x).exp_m1() / k.exp_m1(),
+            Curve::Power(p) => x.powf(*p),
+            Curve::Piecewise(points) => piecewise(points, x),
+        };
+        y.copysign(v)
+    }
+}
+
+fn piecewise(points: &[(f64, f64)], x: f64) -> f64 {
+    let mut last = (0.0, 0.0);
+    for &(x1, y1) in points {
+        if x <= x1 {
+            let (x0, y0) = last;
+            return if x1 > x0 {
+                y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+            } else {
+                y1
+            };
+        }
+        last = (x1, y1);
+    }
+    last.1
+}
+
+// A Curve for each axis (x, y, z, rx, ry, rz)
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResponseCurves {
+    pub curves: [Curve; 6],
+    // The values that normalize to 1
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub full_scale: FullScale,
+}
+
+impl ResponseCurves {
+    // One curve for translation and one for rotation, normalized against a
+    // SpaceNavigator's full scale
+    pub fn new(translation: Curve, rotation: Curve) -> ResponseCurves {
+        let (t, r) = (translation, rotation);
+        ResponseCurves::per_axis([t.clone(), t.clone(), t, r.clone(), r.clone(), r])
+    }
+    pub fn per_axis(curves: [Curve; 6]) -> ResponseCurves {
+        ResponseCurves {
+            curves,
+            full_scale: FullScale::default(),
+        }
+    }
+    // E.g. DeviceModel::full_scale
+    pub fn full_scale(mut self, full_scale: FullScale) -> Self {
+        self.full_scale = full_scale;
+        self
+    }
+    pub fn apply(&self, motion: &MotionEvent) -> MotionEvent {
+        let (t, r) = (self.full_scale.translation, self.full_scale.rotation);
+        let full_scale = [t, t, t, r, r, r].map(f64::from);
+        let axes = axes(motion);
+        with_axes(
+            motion,
+            [0, 1, 2, 3, 4, 5].map(|i| {
+                let v = f64::from(axes[i]) / full_scale[i];
+                (self.curves[i].apply(v) * full_scale[i]).round() as i32
+            }),
+        )
+    }
+}
+
+impl MotionFilter for ResponseCurves {
+    fn filter(&mut self, motion: MotionEvent, _at: Instant) -> Option<MotionEvent> {
+        Some(self.apply(&motion))
+    }
+}
@@ -0,0 +1,91 @@
+// Deadzones for devices that drift a few counts at rest. Translation (t()) and
+// rotation (r()) each get their own, either per axis or on the length of the
+// whole vector. What survives is rescaled so the output starts from zero at
+// the edge of the deadzone and still reaches full scale at full deflection,
+// instead of jumping from 0 to the threshold.
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Deadzone {
+    #[default]
+    Off,
+    // Zeroes each axis on its own while its magnitude is within the
+    // threshold for it, e.g. to ignore a twitchy z
+    PerAxis([i32; 3]),
+    // Zeroes the whole vector while its length is within the threshold, so
+    // diagonal motion is treated the same as motion along one axis
+    Radial(i32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeadzoneFilter {
+    translation: Deadzone,
+    rotation: Deadzone,
+    #[cfg_attr(feature = "serde", serde(default))]
+    full_scale: FullScale,
+}
+
+impl DeadzoneFilter {
+    pub fn new(translation: Deadzone, rotation: Deadzone) -> DeadzoneFilter {
+        DeadzoneFilter {
+            translation,
+            rotation,
+            full_scale: FullScale::default(),
+        }
+    }
+    // The values at full deflection, which stay where they are after
+    // rescaling, e.g. DeviceModel::full_scale. Defaults to a SpaceNavigator's.
+    pub fn full_scale(mut self, full_scale: FullScale) -> Self {
+        self.full_scale = full_scale;
+        self
+    }
+    pub fn apply(&self, motion: &MotionEvent) -> MotionEvent {
+        let (x, y, z) = filter(self.translation, self.full_scale.translation, motion.t());
+        let (rx, ry, rz) = filter(self.rotation, self.full_scale.rotation, motion.r());
+        MotionEvent {
+            x,
+            y,
+            z,
+            rx,
+            ry,
+            rz,
+            period: motion.period,
+        }
+    }
+}
+
+fn filter(deadzone: Deadzone, full_scale: f32, (x, y, z): (i32, i32, i32)) -> (i32, i32, i32) {
+    let v = [x, y, z].map(f64::from);
+    let out = match deadzone {
+        Deadzone::Off => return (x, y, z),
+        Deadzone::PerAxis(thresholds) => {
+            [0, 1, 2].map(|i| v[i].signum() * rescale(v[i].abs(), thresholds[i], full_scale))
+        }
+        Deadzone::Radial(threshold) => {
+            let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+            let scale = match len {
+                0.0 => 0.0,
+                len => rescale(len, threshold, full_scale) / len,
+            };
+            v.map(|c| c * scale)
+        }
+    };
+    let [x, y, z] = out.map(|c| c.round() as i32);
+    (x, y, z)
+}
+
+// Maps a magnitude from [threshold, full_scale] onto [0, full_scale]. A
+// threshold at or past full scale is clamped to just below it, so full
+// deflection still gets through and the output never jumps.
+fn rescale(magnitude: f64, threshold: i32, full_scale: f32) -> f64 {
+    let full_scale = f64::from(full_scale).max(1.0);
+    let threshold = f64::from(threshold.max(0)).min(full_scale - 1.0);
+    if magnitude <= threshold {
+        0.0
+    } else {
+        (magnitude - threshold) * full_scale / (full_scale - threshold)
+    }
+}
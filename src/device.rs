@@ -0,0 +1,14 @@
+// What is plugged into the daemon, as reported by the protocol v1 device
+// queries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub name: String,
+    // Device node the daemon opened, e.g. /dev/input/event5
+    pub path: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    // spacenavd's device type enum
+    pub devtype: i32,
+    pub num_axes: usize,
+    pub num_buttons: usize,
+}
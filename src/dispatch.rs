@@ -0,0 +1,226 @@
+// One reader thread per daemon connection, fanning every event out to each
+// Connection handle that shares it. Every handle has a datagram socket of its
+// own, so its fd is readable exactly when it has something to read and no
+// handle can take another's events.
+use super::*;
+use crate::proto::{Packet, PACKET_SIZE};
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::thread::{self, JoinHandle};
+
+// Shared by every handle. Dropping the last one stops the thread and then
+// closes the backend.
+#[derive(Debug)]
+pub(crate) struct Shared {
+    dispatcher: Arc<Dispatcher>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct Dispatcher {
+    backend: Backend,
+    fd: RawFd,
+    subscribers: Mutex<Vec<Arc<Subscriber>>>,
+    // Wakes the thread to stop, or to pick up events a request read early
+    wake: CancelHandle,
+    stop: AtomicBool,
+    // Why the backend stopped producing events, once it has
+    failed: OnceLock<Error>,
+}
+
+// The sending half of one handle's socket, and which events it wants
+#[derive(Debug)]
+pub(crate) struct Subscriber {
+    tx: UnixDatagram,
+    // The handle's receiving half again, to make room when it is full
+    rx: UnixDatagram,
+    pub(crate) mask: AtomicI32,
+    // Events discarded to make room
+    pub(crate) dropped: AtomicU64,
+}
+
+impl Shared {
+    pub(crate) fn start(backend: Backend, fd: RawFd) -> Result<Arc<Shared>> {
+        let dispatcher = Arc::new(Dispatcher {
+            backend,
+            fd,
+            subscribers: Mutex::default(),
+            wake: CancelHandle::new()?,
+            stop: AtomicBool::new(false),
+            failed: OnceLock::new(),
+        });
+        let thread = {
+            let dispatcher = dispatcher.clone();
+            thread::Builder::new()
+                .name("spacenav-dispatch".into())
+                .spawn(move || dispatcher.run())
+                .map_err(|e| Error::from_io(Operation::Open, &e))?
+        };
+        Ok(Arc::new(Shared {
+            dispatcher,
+            thread: Some(thread),
+        }))
+    }
+    pub(crate) fn backend(&self) -> &Backend {
+        &self.dispatcher.backend
+    }
+    // A new handle's subscription, and the socket it reads its events from
+    pub(crate) fn subscribe(&self, mask: i32) -> Result<(Arc<Subscriber>, UnixDatagram)> {
+        let (rx, tx, oldest) = UnixDatagram::pair()
+            .and_then(|(rx, tx)| {
+                rx.set_nonblocking(true)?;
+                tx.set_nonblocking(true)?;
+                let oldest = rx.try_clone()?;
+                Ok((rx, tx, oldest))
+            })
+            .map_err(|e| Error::from_io(Operation::Open, &e))?;
+        let subscriber = Arc::new(Subscriber {
+            tx,
+            rx: oldest,
+            mask: AtomicI32::new(mask),
+            dropped: AtomicU64::new(0),
+        });
+        self.dispatcher.lock().push(subscriber.clone());
+        Ok((subscriber, rx))
+    }
+    pub(crate) fn unsubscribe(&self, subscriber: &Arc<Subscriber>) {
+        self.dispatcher
+            .lock()
+            .retain(|other| !Arc::ptr_eq(other, subscriber));
+    }
+    // Every event type some handle still wants
+    #[cfg(feature = "native")]
+    pub(crate) fn combined_mask(&self) -> i32 {
+        let subscribers = self.dispatcher.lock();
+        subscribers
+            .iter()
+            .fold(0, |mask, s| mask | s.mask.load(Ordering::Relaxed))
+    }
+    // Has the thread look for events again, e.g. ones the native client
+    // queued while waiting for a response
+    #[cfg(feature = "native")]
+    pub(crate) fn wake(&self) {
+        self.dispatcher.wake.cancel();
+    }
+    // What a handle with nothing left to read should report: None while the
+    // backend is fine
+    pub(crate) fn failure(&self, op: Operation) -> Option<Error> {
+        self.dispatcher.failed.get().map(|e| match e {
+            Error::Disconnected { .. } => Error::Disconnected { op },
+            e => e.clone(),
+        })
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        self.dispatcher.stop.store(true, Ordering::SeqCst);
+        self.dispatcher.wake.cancel();
+        if let Some(thread) = self.thread.take() {
+            // The thread never panics on purpose; if it did, there is nothing
+            // left to clean up
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Dispatcher {
+    fn lock(&self) -> MutexGuard<'_, Vec<Arc<Subscriber>>> {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    fn run(&self) {
+        let op = Operation::PollEvent;
+        loop {
+            if let Err(e) = self.pump() {
+                return self.fail(e);
+            }
+            if self.stop.load(Ordering::SeqCst) {
+                return;
+            }
+            // Checked before every poll rather than only after being woken,
+            // so a wakeup that raced with the previous reset still counts
+            if self.wake.is_cancelled() {
+                self.wake.reset();
+                continue;
+            }
+            match sys::poll_readable(self.fd, Some(self.wake.fd()), None) {
+                Ok(sys::Readiness::Readable | sys::Readiness::Woken | sys::Readiness::TimedOut) => {
+                }
+                // Hand out whatever was still buffered before giving up
+                Ok(sys::Readiness::HungUp) => match self.pump() {
+                    Ok(0) => return self.fail(Error::Disconnected { op }),
+                    Ok(_) => {}
+                    Err(e) => return self.fail(e),
+                },
+                Err(e) => return self.fail(Error::from_io(op, &e)),
+            }
+        }
+    }
+    // Fans out every event the backend has ready, returning how many
+    fn pump(&self) -> Result<usize> {
+        let mut n = 0;
+        loop {
+            let packet = match self.backend.poll() {
+                Ok(Some(event)) => proto::encode(&event),
+                Ok(None) => return Ok(n),
+                // Passed on as is, for the handle to report
+                Err(Error::UnknownEvent { type_ }) => [type_, 0, 0, 0, 0, 0, 0, 0],
+                Err(e) => return Err(e),
+            };
+            self.broadcast(&packet);
+            n += 1;
+        }
+    }
+    fn broadcast(&self, packet: &Packet) {
+        let bytes = proto::to_bytes(packet);
+        for subscriber in self.lock().iter() {
+            if proto::in_mask(packet, subscriber.mask.load(Ordering::Relaxed)) {
+                subscriber.send(&bytes);
+            }
+        }
+    }
+    fn fail(&self, e: Error) {
+        let _ = self.failed.set(e);
+        // An empty datagram wakes handles blocked on their fd. If a socket is
+        // full its handle finds out once it has read the rest.
+        for subscriber in self.lock().iter() {
+            let _ = subscriber.tx.send(&[]);
+        }
+    }
+}
+
+impl Subscriber {
+    // A handle that stopped reading loses its oldest events once its socket
+    // is full, rather than holding up every other handle or missing the
+    // latest state, e.g. a button release
+    fn send(&self, bytes: &[u8]) {
+        match self.tx.send(bytes) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                let mut oldest = [0u8; PACKET_SIZE];
+                if self.rx.recv(&mut oldest).is_ok() {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                // Still full only if the handle is gone or broken
+                let _ = self.tx.send(bytes);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Reads the next packet meant for a handle. Ok(None) covers both "nothing yet"
+// and the empty datagram sent on failure; Shared::failure tells them apart.
+pub(crate) fn recv(rx: &UnixDatagram, op: Operation) -> Result<Option<Packet>> {
+    let mut buf = [0u8; PACKET_SIZE];
+    loop {
+        match rx.recv(&mut buf) {
+            Ok(PACKET_SIZE) => return Ok(Some(proto::parse(&buf))),
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(Error::from_io(op, &e)),
+        }
+    }
+}
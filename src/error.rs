@@ -0,0 +1,175 @@
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// The call that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Operation {
+    Open,
+    Close,
+    Fd,
+    Sensitivity,
+    WaitEvent,
+    PollEvent,
+    Decode,
+    DeviceInfo,
+    Config,
+    EventMask,
+    ClientName,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::Open => "open",
+            Operation::Close => "close",
+            Operation::Fd => "fd",
+            Operation::Sensitivity => "sensitivity",
+            Operation::WaitEvent => "wait event",
+            Operation::PollEvent => "poll event",
+            Operation::Decode => "decode event",
+            Operation::DeviceInfo => "device info",
+            Operation::Config => "daemon config",
+            Operation::EventMask => "event mask",
+            Operation::ClientName => "client name",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    // spacenavd isn't running, or isn't listening where we looked
+    NotRunning { op: Operation, errno: i32 },
+    // The daemon socket exists but we aren't allowed to talk to it
+    PermissionDenied { op: Operation, errno: i32 },
+    // The daemon hung up on us
+    Disconnected { op: Operation },
+    // The daemon sent an event type this crate doesn't understand
+    UnknownEvent { type_: i32 },
+    // The daemon or backend is too old for this request
+    Unsupported { op: Operation },
+    // The daemon understood the request but reported failure
+    Rejected { op: Operation, status: i32 },
+    // Any other failure reported through errno
+    Os { op: Operation, errno: i32 },
+    // A failure without an errno, e.g. a socket path that is too long
+    Io { op: Operation, kind: io::ErrorKind },
+}
+
+impl Error {
+    // Classifies an io error coming out of `op`
+    pub(crate) fn from_io(op: Operation, err: &io::Error) -> Error {
+        let Some(errno) = err.raw_os_error() else {
+            return match err.kind() {
+                io::ErrorKind::UnexpectedEof => Error::Disconnected { op },
+                kind => Error::Io { op, kind },
+            };
+        };
+        match err.kind() {
+            io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => {
+                Error::NotRunning { op, errno }
+            }
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied { op, errno },
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => Error::Disconnected { op },
+            _ => Error::Os { op, errno },
+        }
+    }
+
+    // Builds an error from errno right after a failed libspnav call, which
+    // has to be preceded by sys::clear_errno
+    #[cfg(feature = "libspnav")]
+    pub(crate) fn last_os_error(op: Operation) -> Error {
+        match io::Error::last_os_error() {
+            // libspnav fails without setting errno when the daemon hangs up or
+            // was never connected
+            e if e.raw_os_error() == Some(0) => Error::Disconnected { op },
+            e => Error::from_io(op, &e),
+        }
+    }
+
+    // The daemon didn't answer in time
+    #[cfg(feature = "native")]
+    pub(crate) fn timed_out(op: Operation) -> Error {
+        Error::Os {
+            op,
+            errno: libc::ETIMEDOUT,
+        }
+    }
+
+    pub fn operation(&self) -> Operation {
+        match *self {
+            Error::NotRunning { op, .. }
+            | Error::PermissionDenied { op, .. }
+            | Error::Disconnected { op }
+            | Error::Unsupported { op }
+            | Error::Rejected { op, .. }
+            | Error::Os { op, .. }
+            | Error::Io { op, .. } => op,
+            Error::UnknownEvent { .. } => Operation::Decode,
+        }
+    }
+
+    pub fn errno(&self) -> Option<i32> {
+        match *self {
+            Error::NotRunning { errno, .. }
+            | Error::PermissionDenied { errno, .. }
+            | Error::Os { errno, .. } => Some(errno),
+            Error::Disconnected { .. }
+            | Error::UnknownEvent { .. }
+            | Error::Unsupported { .. }
+            | Error::Rejected { .. }
+            | Error::Io { .. } => None,
+        }
+    }
+
+    // Whether trying again later may succeed, e.g. once the daemon restarts
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::NotRunning { .. } | Error::Disconnected { .. } => true,
+            Error::Os { errno, .. } => matches!(
+                io::Error::from_raw_os_error(*errno).kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            Error::PermissionDenied { .. }
+            | Error::UnknownEvent { .. }
+            | Error::Unsupported { .. }
+            | Error::Rejected { .. }
+            | Error::Io { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotRunning { op, errno } => {
+                write!(f, "{}: spacenavd is not running (errno {})", op, errno)
+            }
+            Error::PermissionDenied { op, errno } => {
+                write!(f, "{}: permission denied (errno {})", op, errno)
+            }
+            Error::Disconnected { op } => write!(f, "{}: spacenavd closed the connection", op),
+            Error::UnknownEvent { type_ } => write!(f, "unknown event type {}", type_),
+            Error::Unsupported { op } => write!(f, "{}: not supported by this spacenavd", op),
+            Error::Rejected { op, status } => {
+                write!(
+                    f,
+                    "{}: spacenavd rejected the request (status {})",
+                    op, status
+                )
+            }
+            Error::Os { op, errno } => {
+                write!(f, "{}: {}", op, io::Error::from_raw_os_error(*errno))
+            }
+            Error::Io { op, kind } => write!(f, "{}: {}", op, kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,320 @@
+// Processing for motion between the device and the application: deadzones,
+// scaling, inversion, smoothing, response curves and whatever else implements
+// MotionFilter. A Pipeline runs a chain of them, can be put together from
+// configuration data, and wraps any event source. Only motion is filtered;
+// every other event passes through as is.
+use super::*;
+use std::fmt;
+
+pub trait MotionFilter: Send {
+    // Returns the filtered motion, or None to swallow the event. `at` is when
+    // it arrived, for filters that depend on timing.
+    fn filter(&mut self, motion: MotionEvent, at: Instant) -> Option<MotionEvent>;
+    // Forgets any state built up from earlier events, e.g. after a reconnect
+    fn reset(&mut self) {}
+}
+
+impl MotionFilter for DeadzoneFilter {
+    fn filter(&mut self, motion: MotionEvent, _at: Instant) -> Option<MotionEvent> {
+        Some(self.apply(&motion))
+    }
+}
+
+// Multiplies each axis (x, y, z, rx, ry, rz) by a factor
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Scale(pub [f64; 6]);
+
+impl Scale {
+    // One factor for translation and one for rotation
+    pub fn uniform(translation: f64, rotation: f64) -> Scale {
+        let (t, r) = (translation, rotation);
+        Scale([t, t, t, r, r, r])
+    }
+}
+
+impl MotionFilter for Scale {
+    fn filter(&mut self, motion: MotionEvent, _at: Instant) -> Option<MotionEvent> {
+        let axes = axes(&motion);
+        Some(with_axes(
+            &motion,
+            [0, 1, 2, 3, 4, 5].map(|i| (f64::from(axes[i]) * self.0[i]).round() as i32),
+        ))
+    }
+}
+
+// Flips the sign of each axis (x, y, z, rx, ry, rz) marked true
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Invert(pub [bool; 6]);
+
+impl MotionFilter for Invert {
+    fn filter(&mut self, motion: MotionEvent, _at: Instant) -> Option<MotionEvent> {
+        let axes = axes(&motion);
+        Some(with_axes(
+            &motion,
+            [0, 1, 2, 3, 4, 5].map(|i| {
+                if self.0[i] {
+                    axes[i].saturating_neg()
+                } else {
+                    axes[i]
+                }
+            }),
+        ))
+    }
+}
+
+// An exponential moving average over time: the output covers 63% of the way
+// to a new steady input within `time_constant`, however often events arrive
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Smoothing {
+    #[cfg_attr(feature = "serde", serde(with = "millis"))]
+    pub time_constant: Duration,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    state: Option<([f64; 6], Instant)>,
+}
+
+impl Smoothing {
+    pub fn new(time_constant: Duration) -> Smoothing {
+        Smoothing {
+            time_constant,
+            state: None,
+        }
+    }
+}
+
+impl MotionFilter for Smoothing {
+    fn filter(&mut self, motion: MotionEvent, at: Instant) -> Option<MotionEvent> {
+        let input = axes(&motion).map(f64::from);
+        let output = match self.state {
+            Some((last, last_at)) if !self.time_constant.is_zero() => {
+                let dt = at.saturating_duration_since(last_at).as_secs_f64();
+                let alpha = 1.0 - (-dt / self.time_constant.as_secs_f64()).exp();
+                [0, 1, 2, 3, 4, 5].map(|i| last[i] + alpha * (input[i] - last[i]))
+            }
+            _ => input,
+        };
+        self.state = Some((output, at));
+        Some(with_axes(&motion, output.map(|v| v.round() as i32)))
+    }
+    fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+// One stage of a Pipeline, as it appears in configuration data
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum FilterConfig {
+    Deadzone(DeadzoneFilter),
+    Scale(Scale),
+    Invert(Invert),
+    Smoothing(Smoothing),
+    Curves(ResponseCurves),
+}
+
+impl FilterConfig {
+    pub fn build(&self) -> Box<dyn MotionFilter> {
+        match self {
+            FilterConfig::Deadzone(f) => Box::new(f.clone()),
+            FilterConfig::Scale(f) => Box::new(*f),
+            FilterConfig::Invert(f) => Box::new(*f),
+            FilterConfig::Smoothing(f) => Box::new(Smoothing::new(f.time_constant)),
+            FilterConfig::Curves(f) => Box::new(f.clone()),
+        }
+    }
+}
+
+// Runs motion through each filter in turn, stopping at the first that
+// swallows it
+#[derive(Default)]
+pub struct Pipeline {
+    filters: Vec<Box<dyn MotionFilter>>,
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("filters", &self.filters.len())
+            .finish()
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+    pub fn from_config(config: &[FilterConfig]) -> Pipeline {
+        Pipeline {
+            filters: config.iter().map(FilterConfig::build).collect(),
+        }
+    }
+    // Adds a filter after the ones already there
+    pub fn with(mut self, filter: impl MotionFilter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+    pub fn push(&mut self, filter: Box<dyn MotionFilter>) {
+        self.filters.push(filter);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+    // Filters a motion event, passing anything else through. None means a
+    // filter swallowed it.
+    pub fn process<T: Filterable>(&mut self, item: T, at: Instant) -> Option<T> {
+        match item.into_motion() {
+            Ok(motion) => self.filter(motion, at).map(T::from_motion),
+            Err(item) => Some(item),
+        }
+    }
+    // Filters everything `source` yields, timestamped as it is taken from it,
+    // e.g. `pipeline.wrap(conn.iter())`
+    pub fn wrap<I>(self, source: I) -> Filtered<I>
+    where
+        I: Iterator,
+        I::Item: Filterable,
+    {
+        Filtered {
+            pipeline: self,
+            source,
+        }
+    }
+}
+
+impl MotionFilter for Pipeline {
+    fn filter(&mut self, motion: MotionEvent, at: Instant) -> Option<MotionEvent> {
+        self.filters
+            .iter_mut()
+            .try_fold(motion, |motion, filter| filter.filter(motion, at))
+    }
+    fn reset(&mut self) {
+        for filter in &mut self.filters {
+            filter.reset();
+        }
+    }
+}
+
+// What a Pipeline can filter: anything that may carry a MotionEvent
+pub trait Filterable: Sized {
+    // The motion to filter, or the item back if there is none
+    fn into_motion(self) -> std::result::Result<MotionEvent, Self>;
+    fn from_motion(motion: MotionEvent) -> Self;
+}
+
+impl Filterable for MotionEvent {
+    fn into_motion(self) -> std::result::Result<MotionEvent, Self> {
+        Ok(self)
+    }
+    fn from_motion(motion: MotionEvent) -> Self {
+        motion
+    }
+}
+
+impl Filterable for Event {
+    fn into_motion(self) -> std::result::Result<MotionEvent, Self> {
+        match self {
+            Event::Motion(motion) => Ok(motion),
+            event => Err(event),
+        }
+    }
+    fn from_motion(motion: MotionEvent) -> Self {
+        Event::Motion(motion)
+    }
+}
+
+impl Filterable for Result<Event> {
+    fn into_motion(self) -> std::result::Result<MotionEvent, Self> {
+        match self {
+            Ok(Event::Motion(motion)) => Ok(motion),
+            other => Err(other),
+        }
+    }
+    fn from_motion(motion: MotionEvent) -> Self {
+        Ok(Event::Motion(motion))
+    }
+}
+
+impl Filterable for ConnectionEvent {
+    fn into_motion(self) -> std::result::Result<MotionEvent, Self> {
+        match self {
+            ConnectionEvent::Event(Event::Motion(motion)) => Ok(motion),
+            other => Err(other),
+        }
+    }
+    fn from_motion(motion: MotionEvent) -> Self {
+        ConnectionEvent::Event(Event::Motion(motion))
+    }
+}
+
+// An event source with a Pipeline applied, from Pipeline::wrap
+#[derive(Debug)]
+pub struct Filtered<I> {
+    pipeline: Pipeline,
+    source: I,
+}
+
+impl<I> Filtered<I> {
+    pub fn pipeline(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+    pub fn into_inner(self) -> (Pipeline, I) {
+        (self.pipeline, self.source)
+    }
+}
+
+impl<I> Iterator for Filtered<I>
+where
+    I: Iterator,
+    I::Item: Filterable,
+{
+    type Item = I::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let item = self.source.next()?;
+            if let Some(item) = self.pipeline.process(item, Instant::now()) {
+                return Some(item);
+            }
+        }
+    }
+}
+
+// x, y, z, rx, ry, rz
+pub(crate) fn axes(motion: &MotionEvent) -> [i32; 6] {
+    [
+        motion.x, motion.y, motion.z, motion.rx, motion.ry, motion.rz,
+    ]
+}
+
+pub(crate) fn with_axes(motion: &MotionEvent, axes: [i32; 6]) -> MotionEvent {
+    let [x, y, z, rx, ry, rz] = axes;
+    MotionEvent {
+        x,
+        y,
+        z,
+        rx,
+        ry,
+        rz,
+        period: motion.period,
+    }
+}
+
+// Durations as whole milliseconds, which read better in config files
+#[cfg(feature = "serde")]
+mod millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(d.as_millis() as u64)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        u64::deserialize(d).map(Duration::from_millis)
+    }
+}
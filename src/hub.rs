@@ -0,0 +1,279 @@
+// Hands every event from one Connection to any number of independent
+// consumers, e.g. a camera, a HUD and a logger. The hub reads on a thread of
+// its own and copies each event into the bounded queue of every Subscription
+// whose filter it matches; what happens when a queue is full is up to the
+// hub's SlowSubscriber policy.
+use super::*;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+const DEFAULT_CAPACITY: usize = 64;
+
+// What to do with an event for a Subscription whose queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SlowSubscriber {
+    // Make room by discarding the oldest queued event
+    #[default]
+    DropOldest,
+    // Discard the new event
+    DropNewest,
+    // Stop reading until the subscriber catches up, holding up every other
+    // subscriber too
+    Block,
+}
+
+#[derive(Debug)]
+pub struct EventHub {
+    shared: Arc<Shared>,
+    cancel: CancelHandle,
+    thread: Option<JoinHandle<()>>,
+    capacity: usize,
+    policy: SlowSubscriber,
+}
+
+// One consumer's queue. Dropping it unsubscribes.
+#[derive(Debug)]
+pub struct Subscription {
+    channel: Arc<Channel>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<HubState>,
+    stop: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct HubState {
+    channels: Vec<Arc<Channel>>,
+    // Set once the hub stops reading; what new subscribers get told
+    closed: Option<Error>,
+}
+
+#[derive(Debug)]
+struct Channel {
+    types: Vec<EventType>,
+    capacity: usize,
+    policy: SlowSubscriber,
+    state: Mutex<ChannelState>,
+    readable: Condvar,
+    writable: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct ChannelState {
+    queue: VecDeque<Event>,
+    dropped: u64,
+    closed: Option<Error>,
+    unsubscribed: bool,
+}
+
+impl EventHub {
+    // Starts reading from `conn` right away. Subscriptions get room for 64
+    // events and drop the oldest once full, unless changed with `capacity`
+    // and `policy`.
+    pub fn new(conn: Connection) -> Result<EventHub> {
+        let shared = Arc::new(Shared::default());
+        let cancel = CancelHandle::new()?;
+        let thread = {
+            let shared = shared.clone();
+            let cancel = cancel.clone();
+            thread::Builder::new()
+                .name("spacenav-hub".into())
+                .spawn(move || shared.run(&conn, &cancel))
+                .map_err(|e| Error::from_io(Operation::Open, &e))?
+        };
+        Ok(EventHub {
+            shared,
+            cancel,
+            thread: Some(thread),
+            capacity: DEFAULT_CAPACITY,
+            policy: SlowSubscriber::default(),
+        })
+    }
+    // How many events each later Subscription can hold, at least 1
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+    // What later Subscriptions do when they fall behind
+    pub fn policy(mut self, policy: SlowSubscriber) -> Self {
+        self.policy = policy;
+        self
+    }
+    // A new queue for the events matching any of `types`; EventType::Any
+    // takes everything. It only sees events read after it was created.
+    pub fn subscribe(&self, types: &[EventType]) -> Subscription {
+        let channel = Arc::new(Channel {
+            types: types.to_vec(),
+            capacity: self.capacity,
+            policy: self.policy,
+            state: Mutex::default(),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+        });
+        let mut state = self.shared.lock();
+        match &state.closed {
+            Some(e) => channel.lock().closed = Some(e.clone()),
+            None => state.channels.push(channel.clone()),
+        }
+        Subscription { channel }
+    }
+    pub fn subscriber_count(&self) -> usize {
+        let state = self.shared.lock();
+        state
+            .channels
+            .iter()
+            .filter(|c| !c.lock().unsubscribed)
+            .count()
+    }
+}
+
+impl Drop for EventHub {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        self.cancel.cancel();
+        // Frees the thread if it is blocked on a full queue
+        for channel in self.shared.lock().channels.iter() {
+            let _guard = channel.lock();
+            channel.writable.notify_all();
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, HubState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    fn run(&self, conn: &Connection, cancel: &CancelHandle) {
+        let op = Operation::WaitEvent;
+        let e = loop {
+            match conn.wait_cancellable(cancel) {
+                Ok(WaitOutcome::Event(event)) => self.deliver(&event),
+                Ok(_) => break Error::Disconnected { op },
+                // Nobody could have asked for it
+                Err(Error::UnknownEvent { .. }) => {}
+                Err(e) => break e,
+            }
+        };
+        let mut state = self.lock();
+        for channel in state.channels.drain(..) {
+            channel.lock().closed = Some(e.clone());
+            channel.readable.notify_all();
+        }
+        state.closed = Some(e);
+    }
+    fn deliver(&self, event: &Event) {
+        // Copied out so a blocked send doesn't hold up subscribe
+        let channels = {
+            let mut state = self.lock();
+            state.channels.retain(|c| !c.lock().unsubscribed);
+            state.channels.clone()
+        };
+        for channel in channels {
+            if channel.types.iter().any(|t| t.matches(event)) {
+                channel.send(event, &self.stop);
+            }
+        }
+    }
+}
+
+impl Channel {
+    fn lock(&self) -> MutexGuard<'_, ChannelState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    fn send(&self, event: &Event, stop: &AtomicBool) {
+        let mut state = self.lock();
+        if state.queue.len() >= self.capacity {
+            match self.policy {
+                SlowSubscriber::DropOldest => {
+                    state.queue.pop_front();
+                    state.dropped += 1;
+                }
+                SlowSubscriber::DropNewest => {
+                    state.dropped += 1;
+                    return;
+                }
+                SlowSubscriber::Block => {
+                    while state.queue.len() >= self.capacity
+                        && !state.unsubscribed
+                        && !stop.load(Ordering::SeqCst)
+                    {
+                        state = self.writable.wait(state).unwrap_or_else(|e| e.into_inner());
+                    }
+                    if state.queue.len() >= self.capacity {
+                        return;
+                    }
+                }
+            }
+        }
+        state.queue.push_back(event.clone());
+        self.readable.notify_one();
+    }
+    fn recv(&self, op: Operation, deadline: Option<Instant>) -> Result<Option<Event>> {
+        let mut state = self.lock();
+        loop {
+            if let Some(event) = state.queue.pop_front() {
+                self.writable.notify_one();
+                return Ok(Some(event));
+            }
+            if let Some(e) = &state.closed {
+                return Err(match e {
+                    Error::Disconnected { .. } => Error::Disconnected { op },
+                    e => e.clone(),
+                });
+            }
+            state = match deadline {
+                None => self.readable.wait(state).unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(None);
+                    }
+                    self.readable
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+    }
+}
+
+impl Subscription {
+    // Returns the next queued event without blocking. Once the hub has
+    // stopped and the queue is empty this gives the reason, e.g.
+    // Error::Disconnected.
+    pub fn try_recv(&self) -> Result<Option<Event>> {
+        self.channel
+            .recv(Operation::PollEvent, Some(Instant::now()))
+    }
+    // Blocks until an event is queued
+    pub fn recv(&self) -> Result<Event> {
+        self.channel
+            .recv(Operation::WaitEvent, None)
+            .map(|event| event.expect("no deadline"))
+    }
+    // Like `recv`, giving None if nothing arrives within `timeout`. A timeout
+    // too large to add to the current time waits as long as `recv`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<Event>> {
+        self.channel
+            .recv(Operation::WaitEvent, Instant::now().checked_add(timeout))
+    }
+    // How many events this subscription lost to its SlowSubscriber policy
+    pub fn dropped(&self) -> u64 {
+        self.channel.lock().dropped
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.channel.lock().unsubscribed = true;
+        self.channel.writable.notify_all();
+    }
+}
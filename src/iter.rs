@@ -0,0 +1,72 @@
+// Iterators over a Connection's events. Both yield decode failures as
+// `Err(Error::UnknownEvent)` and carry on past them; any other error is
+// yielded once and ends the iteration.
+use super::*;
+use std::iter::FusedIterator;
+
+#[derive(Debug)]
+pub struct TryIter<'a> {
+    conn: &'a Connection,
+    done: bool,
+}
+
+#[derive(Debug)]
+pub struct Iter<'a> {
+    conn: &'a Connection,
+    done: bool,
+}
+
+impl<'a> TryIter<'a> {
+    pub(crate) fn new(conn: &'a Connection) -> Self {
+        TryIter { conn, done: false }
+    }
+}
+
+impl<'a> Iter<'a> {
+    pub(crate) fn new(conn: &'a Connection) -> Self {
+        Iter { conn, done: false }
+    }
+}
+
+// Whether an error leaves the connection usable
+fn recoverable(e: &Error) -> bool {
+    matches!(e, Error::UnknownEvent { .. })
+}
+
+impl Iterator for TryIter<'_> {
+    type Item = Result<Event>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.conn.try_poll() {
+            Ok(Some(event)) => Some(Ok(event)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = !recoverable(&e);
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl FusedIterator for TryIter<'_> {}
+
+impl Iterator for Iter<'_> {
+    type Item = Result<Event>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let event = self.conn.wait();
+        if let Err(e) = &event {
+            self.done = !recoverable(e);
+        }
+        Some(event)
+    }
+}
+
+impl FusedIterator for Iter<'_> {}
@@ -1,36 +1,138 @@
-use lazy_static::lazy_static;
+#[cfg(feature = "libspnav")]
 use libspnav_bindings as libspnav;
-use std::convert::{From, Into, TryFrom};
-use std::sync::Mutex;
+use std::convert::From;
+use std::convert::TryFrom;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+#[cfg(feature = "libspnav")]
+use std::sync::{Condvar, Mutex, Weak};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, Copy)]
+#[cfg(not(any(feature = "native", feature = "libspnav", feature = "x11")))]
+compile_error!("enable at least one backend: the `native`, `libspnav` or `x11` feature");
+
+mod builder;
+mod cancel;
+mod config;
+mod curve;
+mod deadzone;
+mod device;
+mod dispatch;
+mod error;
+mod filter;
+mod hub;
+mod iter;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+#[cfg(feature = "native")]
+pub mod native;
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+pub mod nonblocking;
+mod normalize;
+mod pose;
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+mod proto;
+mod reader;
+mod reconnect;
+mod state;
+mod sys;
+#[cfg(feature = "x11")]
+pub mod x11;
+
+pub use builder::{BackendKind, ConnectionBuilder};
+pub use cancel::CancelHandle;
+pub use config::DaemonConfig;
+pub use curve::{Curve, ResponseCurves};
+pub use deadzone::{Deadzone, DeadzoneFilter};
+pub use device::DeviceInfo;
+pub use error::{Error, Operation, Result};
+pub use filter::{
+    FilterConfig, Filterable, Filtered, Invert, MotionFilter, Pipeline, Scale, Smoothing,
+};
+pub use hub::{EventHub, SlowSubscriber, Subscription};
+pub use iter::{Iter, TryIter};
+pub use normalize::{Calibration, DeviceModel, FullScale, NormalizedMotion, Normalizer};
+pub use pose::{Frame, Pose, PoseIntegrator, Quaternion};
+pub use reader::{EventReceiver, ReaderHandle, Receiver};
+pub use reconnect::{ConnectionEvent, ReconnectingConnection};
+pub use state::{DeviceState, StateSnapshot};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum EventType {
     Any,
     Motion,
     Button,
+    Device,
+    Config,
+    RawAxis,
+    RawButton,
 }
 
 const SPNAV_EVENT_ANY: i32 = 0;
 const SPNAV_EVENT_MOTION: i32 = 1;
 const SPNAV_EVENT_BUTTON: i32 = 2;
+const SPNAV_EVENT_DEV: i32 = 3;
+const SPNAV_EVENT_CFG: i32 = 4;
+const SPNAV_EVENT_RAWAXIS: i32 = 5;
+const SPNAV_EVENT_RAWBUTTON: i32 = 6;
 
-impl Into<i32> for EventType {
-    fn into(self) -> i32 {
-        match self {
+impl From<EventType> for i32 {
+    fn from(t: EventType) -> i32 {
+        match t {
             EventType::Any => SPNAV_EVENT_ANY,
             EventType::Motion => SPNAV_EVENT_MOTION,
             EventType::Button => SPNAV_EVENT_BUTTON,
+            EventType::Device => SPNAV_EVENT_DEV,
+            EventType::Config => SPNAV_EVENT_CFG,
+            EventType::RawAxis => SPNAV_EVENT_RAWAXIS,
+            EventType::RawButton => SPNAV_EVENT_RAWBUTTON,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+impl EventType {
+    // Whether `event` is of this type; Any matches everything
+    pub fn matches(self, event: &Event) -> bool {
+        self == EventType::Any || self == event.event_type()
+    }
+}
+
+// Device, Config and the raw events are only sent by spacenavd/libspnav 1.x
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Event {
     Motion(MotionEvent),
     Button(ButtonEvent),
+    Device(DeviceEvent),
+    Config(ConfigEvent),
+    RawAxis(RawAxisEvent),
+    RawButton(ButtonEvent),
 }
 
-#[derive(Debug, Clone)]
+impl Event {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            Event::Motion(_) => EventType::Motion,
+            Event::Button(_) => EventType::Button,
+            Event::Device(_) => EventType::Device,
+            Event::Config(_) => EventType::Config,
+            Event::RawAxis(_) => EventType::RawAxis,
+            Event::RawButton(_) => EventType::RawButton,
+        }
+    }
+}
+
+// What a wait with a deadline or a CancelHandle ended with. Running out of
+// time or being cancelled is not an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WaitOutcome {
+    Event(Event),
+    TimedOut,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct MotionEvent {
     pub x: i32,
     pub y: i32,
@@ -53,6 +155,7 @@ impl MotionEvent {
     }
 }
 
+#[cfg(feature = "libspnav")]
 impl From<libspnav::spnav_event_motion> for MotionEvent {
     fn from(event: libspnav::spnav_event_motion) -> Self {
         MotionEvent {
@@ -67,12 +170,13 @@ impl From<libspnav::spnav_event_motion> for MotionEvent {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ButtonEvent {
     pub press: bool,
     pub bnum: i32,
 }
 
+#[cfg(feature = "libspnav")]
 impl From<libspnav::spnav_event_button> for ButtonEvent {
     fn from(event: libspnav::spnav_event_button) -> Self {
         ButtonEvent {
@@ -82,10 +186,41 @@ impl From<libspnav::spnav_event_button> for ButtonEvent {
     }
 }
 
+// A device was plugged in or removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceEvent {
+    pub added: bool,
+    pub id: i32,
+    pub devtype: i32,
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+// Some client changed the daemon configuration. `cfg` names the setting and
+// `data` holds its new value(s).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigEvent {
+    pub cfg: i32,
+    pub data: [i32; 6],
+}
+
+// An unprocessed axis reading, before the daemon applies sensitivity, deadzone
+// and axis mapping
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawAxisEvent {
+    pub idx: i32,
+    pub value: i32,
+}
+
+#[cfg(feature = "libspnav")]
 impl TryFrom<libspnav::spnav_event> for Event {
-    type Error = ();
-    fn try_from(event: libspnav::spnav_event) -> Result<Self, Self::Error> {
+    type Error = Error;
+    fn try_from(event: libspnav::spnav_event) -> Result<Self> {
         unsafe {
+            // The bindings predate libspnav 1.x and only know the motion and
+            // button layouts. The newer events are all plain ints, so read them
+            // through the motion fields they overlap.
+            let m = event.motion;
             match event {
                 libspnav::spnav_event {
                     type_: SPNAV_EVENT_MOTION,
@@ -93,57 +228,394 @@ impl TryFrom<libspnav::spnav_event> for Event {
                 libspnav::spnav_event {
                     type_: SPNAV_EVENT_BUTTON,
                 } => Ok(Event::Button(event.button.into())),
-                _ => Err(()),
+                libspnav::spnav_event {
+                    type_: SPNAV_EVENT_DEV,
+                } => Ok(Event::Device(DeviceEvent {
+                    added: m.x == 0,
+                    id: m.y,
+                    devtype: m.z,
+                    vendor_id: m.rx as u16,
+                    product_id: m.ry as u16,
+                })),
+                libspnav::spnav_event {
+                    type_: SPNAV_EVENT_CFG,
+                } => Ok(Event::Config(ConfigEvent {
+                    cfg: m.x,
+                    data: [m.y, m.z, m.rx, m.ry, m.rz, m.period as i32],
+                })),
+                libspnav::spnav_event {
+                    type_: SPNAV_EVENT_RAWAXIS,
+                } => Ok(Event::RawAxis(RawAxisEvent {
+                    idx: m.x,
+                    value: m.y,
+                })),
+                libspnav::spnav_event {
+                    type_: SPNAV_EVENT_RAWBUTTON,
+                } => Ok(Event::RawButton(event.button.into())),
+                libspnav::spnav_event { type_ } => Err(Error::UnknownEvent { type_ }),
             }
         }
     }
 }
 
+// A handle to a daemon connection, which a single reader thread serves. Every
+// handle made with try_clone receives every event, and so does every libspnav
+// Connection, since they all share libspnav's one global connection.
+//
+// Connection is Send and Sync and every method may be called from any thread.
+// Threads that each want all the events should each hold a try_clone; threads
+// sharing one handle (say through an Arc) split its events between them. Daemon
+// requests (device_info, config and so on) from several threads are
+// serialized. `fd` belongs to this handle alone and is readable exactly when
+// it has something to read, so it is safe to poll from any thread or runtime.
+//
+// Events queue up per handle until it is read. Once a handle that isn't being
+// read has a few hundred queued, each new event discards the oldest one, so
+// the latest state (say a button release) is never what gets lost; `dropped`
+// counts them.
 #[derive(Debug)]
 pub struct Connection {
     pub fd: i32,
+    shared: Arc<dispatch::Shared>,
+    subscriber: Arc<dispatch::Subscriber>,
+    rx: UnixDatagram,
 }
 
-lazy_static! {
-    static ref CONN_COUNT: Mutex<usize> = Mutex::new(0);
+// Where a Connection's reader thread gets events from. The native client is
+// preferred when more than one is enabled, then libspnav.
+#[derive(Debug)]
+pub(crate) enum Backend {
+    #[cfg(feature = "native")]
+    Native(native::Client),
+    #[cfg(feature = "libspnav")]
+    Libspnav(LibspnavHandle),
+    // Boxed, an X connection is much bigger than the other clients
+    #[cfg(feature = "x11")]
+    X11(Box<x11::Client>),
+}
+
+impl Backend {
+    pub(crate) fn poll(&self) -> Result<Option<Event>> {
+        match self {
+            #[cfg(feature = "native")]
+            Backend::Native(client) => client.poll(),
+            #[cfg(feature = "libspnav")]
+            Backend::Libspnav(_) => lib::spnav_try_poll_event(),
+            #[cfg(feature = "x11")]
+            Backend::X11(client) => client.poll(),
+        }
+    }
 }
 
 impl Connection {
-    pub fn new() -> Result<Connection, ()> {
-        let mut count = CONN_COUNT.lock().expect("to lock");
-        if *count > 0 {
-            *count += 1;
-            Ok(Connection {
-                fd: lib::spnav_fd()?,
-            })
-        } else {
-            *count = 1;
-            lib::spnav_open()?;
-            Ok(Connection {
-                fd: lib::spnav_fd()?,
-            })
+    pub fn new() -> Result<Connection> {
+        #[cfg(feature = "native")]
+        return Connection::native();
+        #[cfg(all(not(feature = "native"), feature = "libspnav"))]
+        return Connection::libspnav();
+        #[cfg(not(any(feature = "native", feature = "libspnav")))]
+        return Connection::x11();
+    }
+    // Opens a private connection speaking the daemon protocol directly
+    #[cfg(feature = "native")]
+    pub fn native() -> Result<Connection> {
+        native::Client::open()?.try_into()
+    }
+    // Shares the global libspnav connection with every other libspnav Connection
+    #[cfg(feature = "libspnav")]
+    pub fn libspnav() -> Result<Connection> {
+        let mut global = LIBSPNAV.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(shared) = global.upgrade() {
+            return Connection::subscribe(shared, proto::EVMASK_ALL);
         }
+        let handle = LibspnavHandle::new()?;
+        let fd = lib::spnav_fd()?;
+        let shared = dispatch::Shared::start(Backend::Libspnav(handle), fd)?;
+        *global = Arc::downgrade(&shared);
+        Connection::subscribe(shared, proto::EVMASK_ALL)
+    }
+    // Receives Magellan events from the X server named by $DISPLAY
+    #[cfg(feature = "x11")]
+    pub fn x11() -> Result<Connection> {
+        x11::Client::open()?.try_into()
+    }
+    // For connecting with options: socket path, backend, event mask and so on
+    pub fn builder() -> ConnectionBuilder {
+        ConnectionBuilder::new()
+    }
+    // Opens a connection that only receives events of the given types
+    pub fn with_event_mask(types: &[EventType]) -> Result<Connection> {
+        let conn = Connection::new()?;
+        conn.set_event_mask(types)?;
+        Ok(conn)
+    }
+    // A new handle to the same connection, with the same event mask. It only
+    // sees events that arrive from now on. Fails if the process is out of
+    // file descriptors, which is why Connection isn't Clone.
+    pub fn try_clone(&self) -> Result<Connection> {
+        Connection::subscribe(self.shared.clone(), self.mask())
+    }
+    // How many events this handle lost to a full queue, see Connection
+    pub fn dropped(&self) -> u64 {
+        self.subscriber.dropped.load(Ordering::Relaxed)
     }
     pub fn poll(&self) -> Option<Event> {
-        lib::spnav_poll_event()
+        self.try_poll().ok().flatten()
+    }
+    // Like poll, but tells "nothing pending" (Ok(None)) apart from failures
+    pub fn try_poll(&self) -> Result<Option<Event>> {
+        self.next(Operation::PollEvent)
+    }
+    // Stops events of any type not in `types` from reaching this handle, e.g.
+    // &[EventType::Button] for a tool that ignores motion. Other handles are
+    // unaffected. The native backend has the daemon drop what no handle
+    // wants when it can; otherwise they are dropped as they are read.
+    pub fn set_event_mask(&self, types: &[EventType]) -> Result<()> {
+        self.subscriber
+            .mask
+            .store(proto::evmask(types), Ordering::Relaxed);
+        match self.shared.backend() {
+            #[cfg(feature = "native")]
+            Backend::Native(client) => {
+                let result = client.update_mask(|| self.shared.combined_mask());
+                self.shared.wake();
+                result
+            }
+            #[allow(unreachable_patterns)]
+            _ => Ok(()),
+        }
+    }
+    // Asks the daemon what device is attached. Needs the native backend and a
+    // daemon speaking protocol v1, otherwise fails with Error::Unsupported.
+    pub fn device_info(&self) -> Result<DeviceInfo> {
+        match self.shared.backend() {
+            #[cfg(feature = "native")]
+            Backend::Native(client) => {
+                let result = client.device_info();
+                self.shared.wake();
+                result
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::Unsupported {
+                op: Operation::DeviceInfo,
+            }),
+        }
+    }
+    // Names this client in the daemon's logs and config tools. Needs the
+    // native backend and protocol v1, like device_info.
+    #[cfg_attr(not(feature = "native"), allow(unused_variables))]
+    pub fn set_client_name(&self, name: &str) -> Result<()> {
+        match self.shared.backend() {
+            #[cfg(feature = "native")]
+            Backend::Native(client) => {
+                let result = client.set_client_name(name);
+                self.shared.wake();
+                result
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::Unsupported {
+                op: Operation::ClientName,
+            }),
+        }
+    }
+    // The daemon protocol version in use. 0 means a daemon from before
+    // spacenavd 1.0, or the libspnav or X11 backend, none of which take
+    // requests.
+    pub fn protocol_version(&self) -> i32 {
+        match self.shared.backend() {
+            #[cfg(feature = "native")]
+            Backend::Native(client) => client.protocol_version(),
+            #[allow(unreachable_patterns)]
+            _ => 0,
+        }
+    }
+    // Reads and changes the daemon's settings, which apply to every client
+    pub fn config(&self) -> DaemonConfig<'_> {
+        DaemonConfig::new(self)
+    }
+    // Makes a protocol v1 request, which only the native backend can send
+    #[cfg_attr(not(feature = "native"), allow(unused_variables))]
+    pub(crate) fn request(&self, op: Operation, req: i32, data: [i32; 7]) -> Result<[i32; 8]> {
+        match self.shared.backend() {
+            #[cfg(feature = "native")]
+            Backend::Native(client) => {
+                let result = client.call(op, req, data);
+                // Events that came in while waiting were queued by the client
+                self.shared.wake();
+                result
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::Unsupported { op }),
+        }
     }
-    pub fn wait(&self) -> Result<Event, ()> {
-        lib::spnav_wait_event()
+    // Drains the events already pending without blocking
+    pub fn try_iter(&self) -> TryIter<'_> {
+        TryIter::new(self)
+    }
+    // Blocks for each next event; ends once the connection fails
+    pub fn iter(&self) -> Iter<'_> {
+        Iter::new(self)
+    }
+    pub fn wait(&self) -> Result<Event> {
+        match self.wait_deadline(None, None)? {
+            WaitOutcome::Event(event) => Ok(event),
+            outcome => unreachable!("{:?} without a deadline or CancelHandle", outcome),
+        }
+    }
+    // Blocks until an event arrives or `timeout` has passed
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<WaitOutcome> {
+        self.wait_deadline(Instant::now().checked_add(timeout), None)
+    }
+    // Blocks until an event arrives or `deadline` is reached
+    pub fn wait_until(&self, deadline: Instant) -> Result<WaitOutcome> {
+        self.wait_deadline(Some(deadline), None)
+    }
+    // Blocks until an event arrives or `cancel` is triggered from elsewhere
+    pub fn wait_cancellable(&self, cancel: &CancelHandle) -> Result<WaitOutcome> {
+        self.wait_deadline(None, Some(cancel))
+    }
+
+    // Moves the connection onto a thread named "spacenav-reader" that sends
+    // everything it reads to the returned receiver, errors included
+    pub fn spawn_reader(self) -> Result<(EventReceiver, ReaderHandle)> {
+        reader::spawn(self)
+    }
+
+    fn subscribe(shared: Arc<dispatch::Shared>, mask: i32) -> Result<Connection> {
+        let (subscriber, rx) = shared.subscribe(mask)?;
+        Ok(Connection {
+            fd: rx.as_raw_fd(),
+            shared,
+            subscriber,
+            rx,
+        })
+    }
+    fn next(&self, op: Operation) -> Result<Option<Event>> {
+        loop {
+            match dispatch::recv(&self.rx, op)? {
+                // Sent before the mask changed
+                Some(packet) if !proto::in_mask(&packet, self.mask()) => {}
+                Some(packet) => return proto::decode(&packet).map(Some),
+                None => {
+                    return match self.shared.failure(op) {
+                        Some(e) => Err(e),
+                        None => Ok(None),
+                    }
+                }
+            }
+        }
+    }
+    fn mask(&self) -> i32 {
+        self.subscriber.mask.load(Ordering::Relaxed)
+    }
+    fn wait_deadline(
+        &self,
+        deadline: Option<Instant>,
+        cancel: Option<&CancelHandle>,
+    ) -> Result<WaitOutcome> {
+        let op = Operation::WaitEvent;
+        loop {
+            if cancel.is_some_and(CancelHandle::is_cancelled) {
+                return Ok(WaitOutcome::Cancelled);
+            }
+            if let Some(event) = self.next(op)? {
+                return Ok(WaitOutcome::Event(event));
+            }
+            match sys::poll_readable(self.fd, cancel.map(CancelHandle::fd), deadline)
+                .map_err(|e| Error::from_io(op, &e))?
+            {
+                sys::Readiness::Readable | sys::Readiness::Woken => {}
+                sys::Readiness::TimedOut => return Ok(WaitOutcome::TimedOut),
+                // Whatever was still buffered has been drained above
+                sys::Readiness::HungUp => match self.next(op)? {
+                    Some(event) => return Ok(WaitOutcome::Event(event)),
+                    None => return Err(Error::Disconnected { op }),
+                },
+            }
+        }
     }
 }
 
 impl Drop for Connection {
     fn drop(&mut self) {
-        let mut count = CONN_COUNT.lock().expect("to lock");
-        if *count == 1 {
-            *count = 0;
-            lib::spnav_close().expect("to close");
-        } else {
-            *count -= 1;
+        self.shared.unsubscribe(&self.subscriber);
+    }
+}
+
+impl AsRawFd for Connection {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl AsFd for Connection {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.rx.as_fd()
+    }
+}
+
+// Starts a reader thread for the client
+#[cfg(feature = "native")]
+impl TryFrom<native::Client> for Connection {
+    type Error = Error;
+    fn try_from(client: native::Client) -> Result<Self> {
+        let fd = client.fd();
+        let shared = dispatch::Shared::start(Backend::Native(client), fd)?;
+        Connection::subscribe(shared, proto::EVMASK_ALL)
+    }
+}
+
+#[cfg(feature = "x11")]
+impl TryFrom<x11::Client> for Connection {
+    type Error = Error;
+    fn try_from(client: x11::Client) -> Result<Self> {
+        let fd = client.fd();
+        let shared = dispatch::Shared::start(Backend::X11(Box::new(client)), fd)?;
+        Connection::subscribe(shared, proto::EVMASK_ALL)
+    }
+}
+
+// The reader of the global libspnav connection, while any handle is using it
+#[cfg(feature = "libspnav")]
+static LIBSPNAV: Mutex<Weak<dispatch::Shared>> = Mutex::new(Weak::new());
+// Whether libspnav is open, which it still is for a moment after the last
+// handle has gone
+#[cfg(feature = "libspnav")]
+static LIBSPNAV_OPEN: Mutex<bool> = Mutex::new(false);
+#[cfg(feature = "libspnav")]
+static LIBSPNAV_CLOSED: Condvar = Condvar::new();
+
+// Owns the global libspnav connection, closing it when dropped
+#[cfg(feature = "libspnav")]
+#[derive(Debug)]
+pub(crate) struct LibspnavHandle(());
+
+#[cfg(feature = "libspnav")]
+impl LibspnavHandle {
+    fn new() -> Result<LibspnavHandle> {
+        let mut open = LIBSPNAV_OPEN.lock().unwrap_or_else(|e| e.into_inner());
+        while *open {
+            open = LIBSPNAV_CLOSED
+                .wait(open)
+                .unwrap_or_else(|e| e.into_inner());
         }
+        lib::spnav_open()?;
+        *open = true;
+        Ok(LibspnavHandle(()))
+    }
+}
+
+#[cfg(feature = "libspnav")]
+impl Drop for LibspnavHandle {
+    fn drop(&mut self) {
+        // Fails if the daemon already hung up, which leaves nothing to close
+        let _ = lib::spnav_close();
+        *LIBSPNAV_OPEN.lock().unwrap_or_else(|e| e.into_inner()) = false;
+        LIBSPNAV_CLOSED.notify_all();
     }
 }
 
+#[cfg(feature = "libspnav")]
 pub mod lib {
     use super::*;
 
@@ -152,10 +624,11 @@ pub mod lib {
      * protocol, and it is *NOT* compatible with the 3D connexion driver. If you wish
      * to remain compatible, use the X11 protocol (spnav_x11_open, see below).
      */
-    pub fn spnav_open() -> Result<(), ()> {
+    pub fn spnav_open() -> Result<()> {
+        sys::clear_errno();
         unsafe {
             if libspnav::spnav_open() == -1 {
-                Err(())
+                Err(Error::last_os_error(Operation::Open))
             } else {
                 Ok(())
             }
@@ -166,10 +639,11 @@ pub mod lib {
      * Returns -1 on failure
      */
     // int spnav_close(void);
-    pub fn spnav_close() -> Result<(), ()> {
+    pub fn spnav_close() -> Result<()> {
+        sys::clear_errno();
         unsafe {
             if libspnav::spnav_close() == -1 {
-                Err(())
+                Err(Error::last_os_error(Operation::Close))
             } else {
                 Ok(())
             }
@@ -184,24 +658,29 @@ pub mod lib {
      * no connection is open / failure occured.
      */
     // int spnav_fd(void);
-    pub fn spnav_fd() -> Result<i32, ()> {
+    pub fn spnav_fd() -> Result<i32> {
+        sys::clear_errno();
         unsafe {
             let fd = libspnav::spnav_fd();
             if fd == -1 {
-                Err(())
+                Err(Error::last_os_error(Operation::Fd))
             } else {
                 Ok(fd)
             }
         }
     }
 
-    /* TODO: document */
+    /* Sets the sensitivity for this client only, on top of the daemon's global
+     * setting (see Connection::config for that). Returns 0 on success, -1 on
+     * failure.
+     */
     // int spnav_sensitivity(double sens);
-    pub fn spnav_sensitivity(sens: f64) -> Result<i32, ()> {
+    pub fn spnav_sensitivity(sens: f64) -> Result<i32> {
+        sys::clear_errno();
         unsafe {
             let v = libspnav::spnav_sensitivity(sens);
             if v == -1 {
-                Err(())
+                Err(Error::last_os_error(Operation::Sensitivity))
             } else {
                 Ok(v)
             }
@@ -210,13 +689,14 @@ pub mod lib {
 
     /* blocks waiting for space-nav events. returns 0 if an error occurs */
     // int spnav_wait_event(spnav_event *event);
-    pub fn spnav_wait_event() -> Result<Event, ()> {
+    pub fn spnav_wait_event() -> Result<Event> {
         let mut event = libspnav::spnav_event {
             type_: SPNAV_EVENT_ANY,
         };
+        sys::clear_errno();
         let t = unsafe { libspnav::spnav_wait_event(&mut event) };
         if t == 0 {
-            Err(())
+            Err(Error::last_os_error(Operation::WaitEvent))
         } else {
             event.try_into()
         }
@@ -227,14 +707,20 @@ pub mod lib {
      */
     // int spnav_poll_event(spnav_event *event);
     pub fn spnav_poll_event() -> Option<Event> {
+        spnav_try_poll_event().ok().flatten()
+    }
+
+    // Like spnav_poll_event, but an event that can't be decoded is an error
+    // rather than looking like an empty queue
+    pub fn spnav_try_poll_event() -> Result<Option<Event>> {
         let mut event = libspnav::spnav_event {
             type_: SPNAV_EVENT_ANY,
         };
         let t = unsafe { libspnav::spnav_poll_event(&mut event) };
         if t == 0 {
-            None
+            Ok(None)
         } else {
-            event.try_into().ok()
+            event.try_into().map(Some)
         }
     }
 
@@ -251,12 +737,1106 @@ pub mod lib {
 #[cfg(test)]
 mod test {
     use super::*;
+    #[cfg(feature = "native")]
+    use mock::MockDaemon;
+    use std::time::Duration;
+
+    #[cfg(any(feature = "native", feature = "x11"))]
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn motion_event() -> MotionEvent {
+        MotionEvent {
+            x: 1,
+            y: -2,
+            z: 3,
+            rx: -4,
+            ry: 5,
+            rz: -6,
+            period: 16,
+        }
+    }
+
+    fn motion() -> Event {
+        Event::Motion(motion_event())
+    }
+
+    fn button(press: bool) -> Event {
+        Event::Button(ButtonEvent { press, bnum: 1 })
+    }
+
+    #[cfg(feature = "native")]
+    fn connect(daemon: &MockDaemon) -> Result<Connection> {
+        let c = Connection::try_from(native::Client::connect(daemon.path())?)?;
+        assert!(daemon.wait_for_clients(1, TIMEOUT));
+        Ok(c)
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn basic() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let c = connect(&daemon)?;
+        assert_eq!(c.poll(), None);
+        daemon.send(&motion());
+        daemon.send(&button(true));
+        assert_eq!(c.wait()?, motion());
+        assert_eq!(c.wait()?, button(true));
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn extended_events() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let c = connect(&daemon)?;
+        let events = [
+            Event::Device(DeviceEvent {
+                added: true,
+                id: 3,
+                devtype: 7,
+                vendor_id: 0x256f,
+                product_id: 0xc635,
+            }),
+            Event::Config(ConfigEvent {
+                cfg: 2,
+                data: [1, 2, 3, 4, 5, 6],
+            }),
+            Event::RawAxis(RawAxisEvent {
+                idx: 4,
+                value: -350,
+            }),
+            Event::RawButton(ButtonEvent {
+                press: true,
+                bnum: 12,
+            }),
+        ];
+        for event in &events {
+            daemon.send(event);
+        }
+        for event in events {
+            let received = c.wait()?;
+            assert!(event.event_type().matches(&received));
+            assert_eq!(received, event);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "libspnav")]
+    #[test]
+    fn libspnav_stale_errno() {
+        // Leaves ENOENT behind, which closing no connection mustn't report
+        assert!(std::fs::File::open("/nonexistent/spacenav").is_err());
+        assert_eq!(
+            lib::spnav_close(),
+            Err(Error::Disconnected {
+                op: Operation::Close
+            })
+        );
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn device_info() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let device = DeviceInfo {
+            name: "3Dconnexion SpaceMouse Enterprise with a long name".into(),
+            path: "/dev/input/event7".into(),
+            vendor_id: 0x256f,
+            product_id: 0xc633,
+            devtype: 12,
+            num_axes: 6,
+            num_buttons: 31,
+        };
+        daemon.set_device(Some(device.clone()));
+        let c = connect(&daemon)?;
+        daemon.send(&button(true));
+        assert_eq!(c.device_info()?, device);
+        // Events that arrived while waiting for responses are kept
+        assert_eq!(c.wait()?, button(true));
+        daemon.set_device(None);
+        assert!(matches!(c.device_info(), Err(Error::Rejected { .. })));
+
+        let old = MockDaemon::start_with_protocol(0).expect("mock daemon");
+        let c = connect(&old)?;
+        assert_eq!(
+            c.device_info(),
+            Err(Error::Unsupported {
+                op: Operation::DeviceInfo
+            })
+        );
+        old.send(&motion());
+        assert_eq!(c.wait()?, motion());
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn event_before_handshake_reply() -> Result<()> {
+        for protocol in [proto::PROTO_VERSION, 0] {
+            let daemon = MockDaemon::start_with_protocol(protocol).expect("mock daemon");
+            daemon.set_greeting(Some(motion()));
+            let c = connect(&daemon)?;
+            assert_eq!(c.protocol_version(), protocol);
+            assert_eq!(c.wait()?, motion());
+            // Everything after it still lines up
+            daemon.send(&button(true));
+            assert_eq!(c.wait()?, button(true));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn daemon_config() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let c = connect(&daemon)?;
+        let config = c.config();
+        assert_eq!(config.sensitivity()?, 1.0);
+        config.set_sensitivity(2.5)?;
+        assert_eq!(config.sensitivity()?, 2.5);
+        config.set_axis_sensitivity([1.0, 2.0, 3.0, 0.5, 0.25, 0.125])?;
+        assert_eq!(
+            config.axis_sensitivity()?,
+            [1.0, 2.0, 3.0, 0.5, 0.25, 0.125]
+        );
+        config.set_deadzone(4, 12)?;
+        assert_eq!(config.deadzone(4)?, 12);
+        config.set_inverted([false, true, true, false, false, true])?;
+        assert_eq!(config.inverted()?, [false, true, true, false, false, true]);
+        config.set_axis_map(1, 2)?;
+        assert_eq!(config.axis_map(1)?, 2);
+        config.set_swap_yz(true)?;
+        assert!(config.swap_yz()?);
+        config.save()?;
+        assert_eq!(daemon.save_count(), 1);
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn event_mask() -> Result<()> {
+        for protocol in [1, 0] {
+            let daemon = MockDaemon::start_with_protocol(protocol).expect("mock daemon");
+            let c = connect(&daemon)?;
+            daemon.send(&motion());
+            daemon.send(&button(true));
+            std::thread::sleep(Duration::from_millis(20));
+            c.set_event_mask(&[EventType::Button])?;
+            if protocol > 0 {
+                let mask = c.request(Operation::EventMask, proto::REQ_GET_EVMASK, [0; 7])?;
+                assert_eq!(mask[1], proto::EVMASK_BUTTON);
+            }
+            daemon.send(&motion());
+            daemon.send(&button(false));
+            assert_eq!(c.wait()?, button(true));
+            assert_eq!(c.wait()?, button(false));
+            assert_eq!(c.poll(), None);
+            c.set_event_mask(&[EventType::Any])?;
+            daemon.send(&motion());
+            assert_eq!(c.wait()?, motion());
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn client_name() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let c = connect(&daemon)?;
+        assert_eq!(c.protocol_version(), 1);
+        let name = "a client name long enough to take two packets";
+        c.set_client_name(name)?;
+        assert_eq!(daemon.client_names(), [Some(name.to_string())]);
+
+        let old = MockDaemon::start_with_protocol(0).expect("mock daemon");
+        let c = connect(&old)?;
+        assert_eq!(c.protocol_version(), 0);
+        assert_eq!(
+            c.set_client_name(name),
+            Err(Error::Unsupported {
+                op: Operation::ClientName
+            })
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn builder() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let c = Connection::builder()
+            .backend(BackendKind::Native)
+            .socket_path(daemon.path())
+            .connect_timeout(TIMEOUT)
+            .event_mask(&[EventType::Motion])
+            .client_name("builder")
+            .connect()?;
+        assert!(daemon.wait_for_clients(1, TIMEOUT));
+        assert_eq!(daemon.client_names(), [Some("builder".to_string())]);
+        daemon.send(&button(true));
+        daemon.send(&motion());
+        assert_eq!(c.wait()?, motion());
+        // Too far off to be a deadline, so there is none
+        Connection::builder()
+            .socket_path(daemon.path())
+            .connect_timeout(Duration::MAX)
+            .connect()?;
+
+        let missing = daemon.path().with_file_name("missing.sock");
+        let result = Connection::builder()
+            .socket_path(missing)
+            .connect_timeout(TIMEOUT)
+            .connect();
+        assert!(matches!(result, Err(Error::NotRunning { .. })));
+        // A configuration mistake, which no amount of retrying fixes
+        let too_long = daemon.path().with_file_name("x".repeat(200));
+        let result = Connection::builder()
+            .socket_path(&too_long)
+            .connect_timeout(TIMEOUT)
+            .connect();
+        let e = result.expect_err("socket path too long");
+        assert_eq!(
+            e,
+            Error::Io {
+                op: Operation::Open,
+                kind: std::io::ErrorKind::InvalidInput
+            }
+        );
+        assert!(!e.is_retryable());
+        let result = Connection::builder()
+            .socket_path(&too_long)
+            .connect_timeout(TIMEOUT)
+            .connect_reconnecting();
+        assert!(matches!(result, Err(Error::Io { .. })));
+        let result = Connection::builder()
+            .backend(BackendKind::X11)
+            .socket_path(daemon.path())
+            .connect();
+        assert!(matches!(result, Err(Error::Unsupported { .. })));
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn reconnecting() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let mut c = Connection::builder()
+            .socket_path(daemon.path())
+            .event_mask(&[EventType::Button])
+            .connect_reconnecting()?
+            .backoff(Duration::from_millis(1), Duration::from_millis(10));
+        assert!(c.is_connected());
+        assert!(daemon.wait_for_clients(1, TIMEOUT));
+        daemon.send(&button(true));
+        assert_eq!(c.wait()?, ConnectionEvent::Event(button(true)));
+        daemon.drop_clients();
+        assert_eq!(c.wait()?, ConnectionEvent::Disconnected);
+        assert_eq!(c.wait()?, ConnectionEvent::Reconnected);
+        assert!(daemon.wait_for_clients(2, TIMEOUT));
+        // The event mask was applied again
+        daemon.send(&motion());
+        daemon.send(&button(false));
+        assert_eq!(c.wait()?, ConnectionEvent::Event(button(false)));
+
+        let missing = daemon.path().with_file_name("missing.sock");
+        let mut c = Connection::builder()
+            .socket_path(missing)
+            .connect_reconnecting()?;
+        assert!(!c.is_connected());
+        assert_eq!(c.poll()?, None);
+        // A backoff too long to schedule or double means no more attempts
+        let mut c = c.backoff(Duration::MAX, Duration::MAX);
+        std::thread::sleep(Duration::from_millis(150));
+        assert_eq!(c.poll()?, None);
+        assert_eq!(c.poll()?, None);
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn stall_and_hang_up() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let c = connect(&daemon)?;
+        daemon.stall();
+        daemon.send(&button(false));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(c.poll(), None);
+        daemon.resume();
+        assert_eq!(c.wait()?, button(false));
+        daemon.drop_clients();
+        assert_eq!(
+            c.wait(),
+            Err(Error::Disconnected {
+                op: Operation::WaitEvent
+            })
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn native_remove_events() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let client = native::Client::connect(daemon.path())?;
+        assert!(daemon.wait_for_clients(1, TIMEOUT));
+        daemon.send(&motion());
+        daemon.send(&button(true));
+        daemon.send(&motion());
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(client.remove_events(EventType::Motion)?, 2);
+        assert_eq!(client.poll()?, Some(button(true)));
+        assert_eq!(client.poll()?, None);
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn spawn_reader() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let (rx, reader) = connect(&daemon)?.spawn_reader()?;
+        daemon.send(&motion());
+        daemon.send_raw([99, 0, 0, 0, 0, 0, 0, 0]);
+        daemon.send(&button(true));
+        daemon.drop_clients();
+        assert_eq!(
+            rx.iter().collect::<Vec<_>>(),
+            vec![
+                Ok(motion()),
+                Err(Error::UnknownEvent { type_: 99 }),
+                Ok(button(true)),
+                Err(Error::Disconnected {
+                    op: Operation::WaitEvent
+                })
+            ]
+        );
+        reader.join();
+
+        let (rx, reader) = connect(&daemon)?.spawn_reader()?;
+        assert!(!reader.is_finished());
+        reader.stop();
+        assert!(rx.recv().is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn iterators() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let c = connect(&daemon)?;
+        assert_eq!(c.try_iter().count(), 0);
+        daemon.send(&motion());
+        daemon.send_raw([99, 0, 0, 0, 0, 0, 0, 0]);
+        daemon.send(&button(true));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(
+            c.try_iter().collect::<Vec<_>>(),
+            vec![
+                Ok(motion()),
+                Err(Error::UnknownEvent { type_: 99 }),
+                Ok(button(true))
+            ]
+        );
+        daemon.send(&button(false));
+        daemon.drop_clients();
+        assert_eq!(
+            c.iter().collect::<Vec<_>>(),
+            vec![
+                Ok(button(false)),
+                Err(Error::Disconnected {
+                    op: Operation::WaitEvent
+                })
+            ]
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn shared_handles() -> Result<()> {
+        fn send_sync<T: Send + Sync>() {}
+        send_sync::<Connection>();
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let c = connect(&daemon)?;
+        let buttons = c.try_clone()?;
+        buttons.set_event_mask(&[EventType::Button])?;
+        let reader = {
+            let c = c.try_clone()?;
+            std::thread::spawn(move || [c.wait(), c.wait()])
+        };
+        daemon.send(&motion());
+        daemon.send(&button(true));
+        assert_eq!(reader.join().unwrap(), [Ok(motion()), Ok(button(true))]);
+        assert_eq!(c.wait()?, motion());
+        assert_eq!(c.wait()?, button(true));
+        assert_eq!(buttons.wait()?, button(true));
+        // The last handle closes the connection, whichever one it is
+        drop(c);
+        daemon.send(&button(false));
+        assert_eq!(buttons.wait()?, button(false));
+        daemon.drop_clients();
+        assert_eq!(
+            buttons.wait(),
+            Err(Error::Disconnected {
+                op: Operation::WaitEvent
+            })
+        );
+        drop(buttons);
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn concurrent_event_masks() -> Result<()> {
+        use std::sync::Barrier;
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let buttons = connect(&daemon)?;
+        let moves = buttons.try_clone()?;
+        let start = Arc::new(Barrier::new(2));
+        let done = Arc::new(Barrier::new(2));
+        // Swaps to the other type at the same time as the main thread
+        let other = {
+            let (start, done) = (start.clone(), done.clone());
+            std::thread::spawn(move || -> Result<()> {
+                for _ in 0..200 {
+                    moves.set_event_mask(&[EventType::Button])?;
+                    start.wait();
+                    moves.set_event_mask(&[EventType::Motion])?;
+                    done.wait();
+                    assert_eq!(moves.wait_timeout(TIMEOUT)?, WaitOutcome::Event(motion()));
+                    done.wait();
+                }
+                Ok(())
+            })
+        };
+        for _ in 0..200 {
+            buttons.set_event_mask(&[EventType::Motion])?;
+            start.wait();
+            buttons.set_event_mask(&[EventType::Button])?;
+            done.wait();
+            // The daemon was left with the mask both ended on
+            daemon.send(&motion());
+            daemon.send(&button(true));
+            assert_eq!(
+                buttons.wait_timeout(TIMEOUT)?,
+                WaitOutcome::Event(button(true))
+            );
+            done.wait();
+        }
+        other.join().unwrap()
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn slow_handle() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        // Events go out in subscription order, so once `c` has one so has `idle`
+        let idle = connect(&daemon)?;
+        let c = idle.try_clone()?;
+        for _ in 0..5000 {
+            daemon.send(&motion());
+        }
+        daemon.send(&button(false));
+        while c.wait()? != button(false) {}
+        // Loses the oldest events, never the last one
+        let mut received = 0;
+        let mut last = None;
+        while let Some(event) = idle.try_poll()? {
+            received += 1;
+            last = Some(event);
+        }
+        assert_eq!(last, Some(button(false)));
+        assert!(idle.dropped() > 0);
+        assert_eq!(received + idle.dropped(), 5001);
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn drop_while_idle() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        for _ in 0..50 {
+            let c = connect(&daemon)?;
+            // Requests wake the dispatcher, racing with the drop below
+            let other = c.try_clone()?;
+            let requests = std::thread::spawn(move || {
+                let _ = other.set_event_mask(&[EventType::Any]);
+                let _ = other.device_info();
+            });
+            requests.join().unwrap();
+            let (done, dropped) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                drop(c);
+                let _ = done.send(());
+            });
+            dropped
+                .recv_timeout(Duration::from_secs(2))
+                .expect("last handle dropped while the daemon is idle");
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn event_hub() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let moved = |x| {
+            Event::Motion(MotionEvent {
+                x,
+                y: 0,
+                z: 0,
+                rx: 0,
+                ry: 0,
+                rz: 0,
+                period: 16,
+            })
+        };
+        let hub = EventHub::new(connect(&daemon)?)?.capacity(2);
+        let all = hub.subscribe(&[EventType::Any]);
+        let buttons = hub.subscribe(&[EventType::Button]);
+        let newest = EventHub::new(connect(&daemon)?)?
+            .capacity(2)
+            .policy(SlowSubscriber::DropNewest);
+        let first = newest.subscribe(&[EventType::Motion]);
+        let blocking = EventHub::new(connect(&daemon)?)?
+            .capacity(1)
+            .policy(SlowSubscriber::Block);
+        let every = blocking.subscribe(&[EventType::Any]);
+        for x in 1..=3 {
+            daemon.send(&moved(x));
+        }
+        daemon.send(&button(true));
+        std::thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(all.try_recv()?, Some(moved(3)));
+        assert_eq!(all.try_recv()?, Some(button(true)));
+        assert_eq!(all.try_recv()?, None);
+        assert_eq!(all.dropped(), 2);
+        assert_eq!(buttons.recv()?, button(true));
+        assert_eq!(buttons.dropped(), 0);
+        assert_eq!(first.recv()?, moved(1));
+        assert_eq!(first.recv()?, moved(2));
+        assert_eq!(first.try_recv()?, None);
+        assert_eq!(first.dropped(), 1);
+        for x in 1..=3 {
+            assert_eq!(every.recv()?, moved(x));
+        }
+        assert_eq!(every.recv()?, button(true));
+        assert_eq!(every.dropped(), 0);
+
+        drop(buttons);
+        daemon.send(&button(false));
+        daemon.send(&button(true));
+        assert_eq!(all.recv()?, button(false));
+        assert_eq!(all.recv_timeout(Duration::MAX)?, Some(button(true)));
+        assert_eq!(hub.subscriber_count(), 1);
+        daemon.drop_clients();
+        assert_eq!(
+            all.recv(),
+            Err(Error::Disconnected {
+                op: Operation::WaitEvent
+            })
+        );
+        assert_eq!(
+            hub.subscribe(&[EventType::Any]).try_recv(),
+            Err(Error::Disconnected {
+                op: Operation::PollEvent
+            })
+        );
+        // Stuck with the second button event, until dropped
+        std::thread::sleep(Duration::from_millis(20));
+        drop(blocking);
+        assert_eq!(every.recv()?, button(false));
+        assert_eq!(
+            every.recv_timeout(Duration::from_millis(10)),
+            Err(Error::Disconnected {
+                op: Operation::WaitEvent
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn device_state() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let press = |bnum| Event::Button(ButtonEvent { press: true, bnum });
+        let release = |bnum| Event::Button(ButtonEvent { press: false, bnum });
+        let mut state = DeviceState::new().motion_timeout(Duration::from_millis(100));
+        assert_eq!(state.snapshot_at(start), StateSnapshot::default());
+
+        state.update_at(&motion(), at(0));
+        state.update_at(&press(0), at(5));
+        state.update_at(&press(1), at(6));
+        state.update_at(&release(1), at(7));
+        let frame = state.snapshot_at(at(16));
+        assert_eq!(
+            frame,
+            StateSnapshot {
+                motion: motion_event(),
+                held: vec![0],
+                pressed: vec![0, 1],
+                released: vec![1],
+            }
+        );
+        assert!(frame.is_held(0) && frame.was_pressed(1) && frame.was_released(1));
+
+        state.update_at(&release(0), at(20));
+        let frame = state.snapshot_at(at(100));
+        assert_eq!(frame.motion, motion_event());
+        assert!(!state.is_held(0));
+        assert_eq!(
+            (frame.held, frame.pressed, frame.released),
+            (vec![], vec![], vec![0])
+        );
+        // No motion for longer than the timeout
+        assert_eq!(state.snapshot_at(at(101)).motion, MotionEvent::default());
+        state.update_at(&press(2), at(110));
+        state.clear();
+        assert_eq!(state.snapshot_at(at(111)), StateSnapshot::default());
+    }
+
+    #[test]
+    fn deadzone() {
+        let m = |x, y, z, rx, ry, rz| MotionEvent {
+            x,
+            y,
+            z,
+            rx,
+            ry,
+            rz,
+            period: 16,
+        };
+        let per_axis = DeadzoneFilter::new(Deadzone::PerAxis([10, 10, 50]), Deadzone::Off)
+            .full_scale(FullScale::new(110.0, 110.0));
+        assert_eq!(
+            per_axis.apply(&m(10, -9, 50, 3, 0, -3)),
+            m(0, 0, 0, 3, 0, -3)
+        );
+        // Picks up from zero at the edge and still reaches full scale
+        assert_eq!(
+            per_axis.apply(&m(11, -11, 60, 0, 0, 0)),
+            m(1, -1, 18, 0, 0, 0)
+        );
+        assert_eq!(
+            per_axis.apply(&m(110, -110, 110, 0, 0, 0)),
+            m(110, -110, 110, 0, 0, 0)
+        );
+
+        let radial = DeadzoneFilter::new(Deadzone::Off, Deadzone::Radial(50))
+            .full_scale(FullScale::new(150.0, 150.0));
+        // Each axis alone is under the threshold, but together they aren't
+        assert_eq!(radial.apply(&m(5, 0, 0, 30, 40, 0)), m(5, 0, 0, 0, 0, 0));
+        assert_eq!(radial.apply(&m(0, 0, 0, 60, 80, 0)), m(0, 0, 0, 45, 60, 0));
+        assert_eq!(
+            radial.apply(&m(0, 0, 0, 0, 0, -150)),
+            m(0, 0, 0, 0, 0, -150)
+        );
+
+        // A threshold past full scale still lets full deflection through
+        let wide = DeadzoneFilter::new(Deadzone::PerAxis([200, 110, 0]), Deadzone::Radial(500))
+            .full_scale(FullScale::new(110.0, 110.0));
+        assert_eq!(wide.apply(&m(109, 109, 0, 109, 0, 0)), m(0, 0, 0, 0, 0, 0));
+        assert_eq!(
+            wide.apply(&m(110, -110, 0, 0, -110, 0)),
+            m(110, -110, 0, 0, -110, 0)
+        );
+    }
+
+    #[test]
+    fn pipeline() {
+        // Swallows motion along z
+        struct NoZ;
+        impl MotionFilter for NoZ {
+            fn filter(&mut self, motion: MotionEvent, _at: Instant) -> Option<MotionEvent> {
+                (motion.z == 0).then_some(motion)
+            }
+        }
+        let m = |x, z| MotionEvent {
+            x,
+            z,
+            period: 16,
+            ..MotionEvent::default()
+        };
+        let now = Instant::now();
+        let mut pipeline = Pipeline::new()
+            .with(DeadzoneFilter::new(Deadzone::Radial(5), Deadzone::Off))
+            .with(NoZ)
+            .with(Scale::uniform(2.0, 1.0))
+            .with(Invert([true, false, false, false, false, false]));
+        assert_eq!(pipeline.len(), 4);
+        assert_eq!(pipeline.filter(m(3, 4), now), Some(m(0, 0)));
+        assert_eq!(pipeline.filter(m(4, 40), now), None);
+        assert_eq!(pipeline.process(button(true), now), Some(button(true)));
+
+        let source = vec![
+            Ok(Event::Motion(m(0, 20))),
+            Ok(button(false)),
+            Err(Error::UnknownEvent { type_: 99 }),
+            Ok(Event::Motion(m(350, 0))),
+        ];
+        assert_eq!(
+            pipeline.wrap(source.into_iter()).collect::<Vec<_>>(),
+            vec![
+                Ok(button(false)),
+                Err(Error::UnknownEvent { type_: 99 }),
+                Ok(Event::Motion(m(-700, 0)))
+            ]
+        );
+        // Saturates rather than overflowing
+        let mut invert = Invert([true, false, true, false, false, false]);
+        assert_eq!(
+            invert.filter(m(i32::MIN, i32::MAX), now),
+            Some(m(i32::MAX, -i32::MAX))
+        );
+
+        let mut smoothing = Smoothing::new(Duration::from_millis(100));
+        let at = |ms| now + Duration::from_millis(ms);
+        assert_eq!(smoothing.filter(m(0, 0), at(0)), Some(m(0, 0)));
+        // One time constant later: 1 - 1/e of the way there
+        assert_eq!(smoothing.filter(m(1000, 0), at(100)), Some(m(632, 0)));
+        smoothing.reset();
+        assert_eq!(smoothing.filter(m(1000, 0), at(101)), Some(m(1000, 0)));
+    }
+
+    #[test]
+    fn response_curves() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        for curve in [Curve::Exponential(3.0), Curve::Power(2.0)] {
+            assert!(close(curve.apply(1.0), 1.0) && close(curve.apply(0.0), 0.0));
+            assert!(close(curve.apply(-0.5), -curve.apply(0.5)));
+            assert!(curve.apply(0.5) < 0.5);
+        }
+        assert!(close(Curve::Power(2.0).apply(-0.5), -0.25));
+        assert!(close(Curve::Exponential(0.0).apply(0.3), 0.3));
+        assert!(close(Curve::Linear.apply(2.0), 1.0));
+        let knee = Curve::Piecewise(vec![(0.5, 0.1), (0.9, 1.0)]);
+        assert!(close(knee.apply(0.25), 0.05));
+        assert!(close(knee.apply(-0.7), -0.55));
+        assert!(close(knee.apply(0.95), 1.0));
+
+        let curves = ResponseCurves::new(Curve::Power(2.0), Curve::Linear)
+            .full_scale(FullScale::new(100.0, 100.0));
+        let m = MotionEvent {
+            x: 50,
+            y: -100,
+            z: 400,
+            rx: 50,
+            ry: 0,
+            rz: -7,
+            period: 16,
+        };
+        assert_eq!(
+            curves.apply(&m),
+            MotionEvent {
+                x: 25,
+                y: -100,
+                z: 100,
+                rx: 50,
+                ry: 0,
+                rz: -7,
+                period: 16,
+            }
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn pipeline_config() {
+        let json = r#"[
+            {"deadzone": {"translation": {"per_axis": [5, 5, 20]}, "rotation": {"radial": 10}}},
+            {"scale": [1.0, 1.0, 0.5, 1.0, 1.0, 1.0]},
+            {"invert": [false, true, false, false, true, false]},
+            {"smoothing": {"time_constant": 50}},
+            {"curves": {
+                "curves": [{"power": 2.0}, {"power": 2.0}, "linear", {"exponential": 1.5},
+                           {"piecewise": [[0.2, 0.05], [1.0, 1.0]]}, "linear"],
+                "full_scale": {"translation": 350, "rotation": 300}
+            }}
+        ]"#;
+        let config: Vec<FilterConfig> = serde_json::from_str(json).unwrap();
+        // The deadzone leaves full_scale out and gets the default
+        assert_eq!(
+            config[0],
+            FilterConfig::Deadzone(DeadzoneFilter::new(
+                Deadzone::PerAxis([5, 5, 20]),
+                Deadzone::Radial(10)
+            ))
+        );
+        assert_eq!(
+            config[3],
+            FilterConfig::Smoothing(Smoothing::new(Duration::from_millis(50)))
+        );
+        assert!(matches!(
+            &config[4],
+            FilterConfig::Curves(c) if c.full_scale == FullScale::new(350.0, 300.0)
+        ));
+        let again: Vec<FilterConfig> =
+            serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+        assert_eq!(again, config);
+        // So can curves
+        let json = r#"{"curves": {"curves": ["linear", "linear", "linear", "linear", "linear", "linear"]}}"#;
+        let curves: FilterConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            curves,
+            FilterConfig::Curves(ResponseCurves::new(Curve::Linear, Curve::Linear))
+        );
+        let again: FilterConfig =
+            serde_json::from_str(&serde_json::to_string(&curves).unwrap()).unwrap();
+        assert_eq!(again, curves);
+        let mut pipeline = Pipeline::from_config(&config);
+        assert_eq!(
+            pipeline.filter(motion_event(), Instant::now()),
+            Some(MotionEvent {
+                period: 16,
+                ..MotionEvent::default()
+            })
+        );
+    }
+
+    #[test]
+    fn normalized_motion() {
+        let m = |x, rz| MotionEvent {
+            x,
+            rz,
+            period: 16,
+            ..MotionEvent::default()
+        };
+        assert_eq!(
+            DeviceModel::from_ids(0x256f, 0xc635),
+            Some(DeviceModel::SpaceMouseCompact)
+        );
+        assert_eq!(
+            DeviceModel::from_ids(0x046d, 0xc626),
+            Some(DeviceModel::SpaceNavigator)
+        );
+        assert_eq!(DeviceModel::from_ids(0x046d, 0xc52b), None);
+
+        let full_scale = FullScale {
+            translation: 200.0,
+            rotation: 400.0,
+        };
+        let n = NormalizedMotion::new(&m(-100, 500), full_scale);
+        assert_eq!(n.t(), (-0.5, 0.0, 0.0));
+        assert_eq!(n.r(), (0.0, 0.0, 1.0));
+        assert_eq!(n.period, 16);
+
+        let mut auto = Normalizer::auto();
+        assert_eq!(auto.normalize(&m(50, -10)).t().0, 0.5);
+        assert_eq!(auto.normalize(&m(400, 0)).t().0, 1.0);
+        assert_eq!(auto.normalize(&m(100, -10)).t().0, 0.25);
+        assert_eq!(
+            auto.full_scale(),
+            [400.0, 100.0, 100.0, 100.0, 100.0, 100.0]
+        );
+        auto.reset();
+        assert_eq!(auto.full_scale(), [100.0; 6]);
+
+        let info = DeviceInfo {
+            name: "3Dconnexion SpaceMouse Pro".into(),
+            path: "/dev/input/event5".into(),
+            vendor_id: 0x046d,
+            product_id: 0xc62b,
+            devtype: 0,
+            num_axes: 6,
+            num_buttons: 15,
+        };
+        let mut fixed = Normalizer::for_device(&info);
+        assert_eq!(
+            fixed.calibration(),
+            Calibration::Fixed(DeviceModel::SpaceMousePro.full_scale())
+        );
+        assert_eq!(fixed.normalize(&m(700, 0)).t().0, 1.0);
+    }
+
+    #[test]
+    fn pose_integrator() {
+        let close = |a: [f64; 3], b: [f64; 3]| (0..3).all(|i| (a[i] - b[i]).abs() < 1e-9);
+        let turn = MotionEvent {
+            rz: 90,
+            period: 100,
+            ..MotionEvent::default()
+        };
+        let forward = MotionEvent {
+            x: 10,
+            period: 100,
+            ..MotionEvent::default()
+        };
+        // 90 counts at a degree per second each, for 10 x 100ms: a quarter turn
+        let mut world = PoseIntegrator::new().scale(1.0, std::f64::consts::PI / 180.0);
+        let mut body = world.clone().frame(Frame::Body);
+        for _ in 0..10 {
+            world.update(&turn);
+            body.update(&turn);
+        }
+        assert!(close(
+            world.pose().rotation.rotate([1.0, 0.0, 0.0]),
+            [0.0, 1.0, 0.0]
+        ));
+        assert_eq!(world.pose(), body.pose());
+        world.update(&forward);
+        body.update(&forward);
+        assert!(close(world.pose().translation, [1.0, 0.0, 0.0]));
+        assert!(close(body.pose().translation, [0.0, 1.0, 0.0]));
+
+        // Long periods are capped, and translation stays within bounds
+        let mut bounded = PoseIntegrator::new().bounds([-1.0; 3], [1.5; 3]);
+        let idle = MotionEvent {
+            period: 5000,
+            ..forward.clone()
+        };
+        assert!(close(bounded.update(&idle).translation, [1.0, 0.0, 0.0]));
+        assert!(close(bounded.update(&forward).translation, [1.5, 0.0, 0.0]));
+        bounded.reset();
+        assert_eq!(bounded.pose(), Pose::default());
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn wait_timeout() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let c = connect(&daemon)?;
+        let timeout = Duration::from_millis(30);
+        let start = Instant::now();
+        assert_eq!(c.wait_timeout(timeout)?, WaitOutcome::TimedOut);
+        assert!(start.elapsed() >= timeout);
+        daemon.send(&motion());
+        assert_eq!(
+            c.wait_until(Instant::now() + TIMEOUT)?,
+            WaitOutcome::Event(motion())
+        );
+        daemon.drop_clients();
+        assert!(c.wait_timeout(TIMEOUT).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn wait_cancellable() -> Result<()> {
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let c = connect(&daemon)?;
+        let cancel = CancelHandle::new()?;
+        let canceller = {
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+                cancel.cancel();
+            })
+        };
+        assert_eq!(c.wait_cancellable(&cancel)?, WaitOutcome::Cancelled);
+        canceller.join().unwrap();
+        cancel.reset();
+        daemon.send(&button(true));
+        assert_eq!(
+            c.wait_cancellable(&cancel)?,
+            WaitOutcome::Event(button(true))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cancel_reset_race() -> Result<()> {
+        use std::sync::Barrier;
+        const ROUNDS: usize = 5000;
+        let cancel = CancelHandle::new()?;
+        let start = Arc::new(Barrier::new(2));
+        let done = Arc::new(Barrier::new(2));
+        let canceller = {
+            let (cancel, start, done) = (cancel.clone(), start.clone(), done.clone());
+            std::thread::spawn(move || {
+                for _ in 0..ROUNDS {
+                    start.wait();
+                    cancel.cancel();
+                    done.wait();
+                }
+            })
+        };
+        for _ in 0..ROUNDS {
+            start.wait();
+            cancel.reset();
+            done.wait();
+            // The fd is readable exactly while cancelled, or waits would spin
+            let woken = sys::poll_readable(cancel.fd(), None, Some(Instant::now())).unwrap();
+            assert_eq!(
+                !matches!(woken, sys::Readiness::TimedOut),
+                cancel.is_cancelled()
+            );
+            cancel.reset();
+        }
+        canceller.join().unwrap();
+        Ok(())
+    }
+
+    #[cfg(all(feature = "native", feature = "tokio"))]
+    #[tokio::test]
+    async fn tokio_connection() -> Result<()> {
+        use futures_core::Stream;
+        use std::pin::Pin;
+
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let mut c = nonblocking::tokio::AsyncConnection::new(connect(&daemon)?)?;
+        daemon.send(&motion());
+        daemon.send(&button(true));
+        assert_eq!(c.next_event().await?, motion());
+        let next = std::future::poll_fn(|cx| Pin::new(&mut c).poll_next(cx)).await;
+        assert_eq!(next, Some(button(true)));
+        // Skipped rather than ending the stream
+        daemon.send_raw([99, 0, 0, 0, 0, 0, 0, 0]);
+        daemon.send(&motion());
+        let next = std::future::poll_fn(|cx| Pin::new(&mut c).poll_next(cx)).await;
+        assert_eq!(next, Some(motion()));
+        daemon.drop_clients();
+        daemon.send(&motion());
+        for _ in 0..2 {
+            let next = std::future::poll_fn(|cx| Pin::new(&mut c).poll_next(cx)).await;
+            assert_eq!(next, None);
+        }
+        Ok(())
+    }
+
+    #[cfg(all(feature = "native", feature = "async-io"))]
+    #[test]
+    fn async_io_connection() -> Result<()> {
+        use futures_core::Stream;
+        use std::pin::Pin;
+
+        let daemon = MockDaemon::start().expect("mock daemon");
+        let mut c = nonblocking::async_io::AsyncConnection::new(connect(&daemon)?)?;
+        async_io::block_on(async {
+            daemon.send(&motion());
+            daemon.send(&button(true));
+            assert_eq!(c.next_event().await?, motion());
+            let next = std::future::poll_fn(|cx| Pin::new(&mut c).poll_next(cx)).await;
+            assert_eq!(next, Some(button(true)));
+            // Skipped rather than ending the stream
+            daemon.send_raw([99, 0, 0, 0, 0, 0, 0, 0]);
+            daemon.send(&motion());
+            let next = std::future::poll_fn(|cx| Pin::new(&mut c).poll_next(cx)).await;
+            assert_eq!(next, Some(motion()));
+            daemon.drop_clients();
+            daemon.send(&motion());
+            for _ in 0..2 {
+                let next = std::future::poll_fn(|cx| Pin::new(&mut c).poll_next(cx)).await;
+                assert_eq!(next, None);
+            }
+            Ok(())
+        })
+    }
 
+    #[cfg(feature = "x11")]
     #[test]
-    fn basic() -> Result<(), ()> {
-        let c = Connection::new()?;
-        println!("{:?}", c);
-        println!("{:?}", c.wait());
+    #[ignore = "needs an X server, e.g. run under xvfb-run"]
+    fn x11_against_fake_driver() -> Result<()> {
+        let driver = mock::FakeMagellanDriver::start(None)?;
+        let c = Connection::x11()?;
+        let window = driver.wait_for_client(TIMEOUT)?.expect("registration");
+        driver.send(window, &motion())?;
+        driver.send(window, &button(true))?;
+        assert_eq!(c.wait()?, motion());
+        assert_eq!(c.wait()?, button(true));
+        c.set_event_mask(&[EventType::Button])?;
+        driver.send(window, &motion())?;
+        driver.send(window, &button(false))?;
+        assert_eq!(c.wait()?, button(false));
         Ok(())
     }
 }
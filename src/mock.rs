@@ -0,0 +1,550 @@
+// An in-process stand-in for spacenavd, listening on a unix socket in a fresh
+// temporary directory. Tests script it by sending events, stalling delivery or
+// dropping every connected client. It speaks protocol v1 unless started with
+// an older version, and answers device queries from `set_device`.
+use super::*;
+use crate::proto::{self, Packet, PACKET_SIZE};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct MockDaemon {
+    dir: PathBuf,
+    path: PathBuf,
+    shared: Arc<Shared>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    clients: Vec<MockClient>,
+    next_id: usize,
+    accepted: usize,
+    // Bytes held back while stalled, and which client they are for (None for
+    // every client)
+    backlog: Vec<(Option<usize>, Vec<u8>)>,
+    stalled: bool,
+    shutdown: bool,
+    protocol: i32,
+    // Sent to each client as it connects, ahead of any reply
+    greeting: Option<Event>,
+    device: Option<DeviceInfo>,
+    config: MockConfig,
+}
+
+// The daemon settings config requests read and change
+#[derive(Debug)]
+struct MockConfig {
+    sens: f32,
+    axis_sens: [f32; 6],
+    deadzones: HashMap<i32, i32>,
+    invert: [bool; 6],
+    axis_map: HashMap<i32, i32>,
+    swap_yz: bool,
+    saves: usize,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        MockConfig {
+            sens: 1.0,
+            axis_sens: [1.0; 6],
+            deadzones: HashMap::new(),
+            invert: [false; 6],
+            axis_map: (0..6).map(|i| (i, i)).collect(),
+            swap_yz: false,
+            saves: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MockClient {
+    id: usize,
+    stream: UnixStream,
+    // Which events it asked for with REQ_SET_EVMASK
+    mask: i32,
+    name: Option<String>,
+    // REQ_SET_NAME packets received so far
+    partial_name: Vec<u8>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl MockDaemon {
+    pub fn start() -> io::Result<MockDaemon> {
+        MockDaemon::start_with_protocol(proto::PROTO_VERSION)
+    }
+    // Protocol 0 behaves like a spacenavd from before 1.0: no requests at all
+    pub fn start_with_protocol(protocol: i32) -> io::Result<MockDaemon> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "spacenav-mock-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        MockDaemon::listen(dir, protocol)
+    }
+    // Listens on `dir`/spnav.sock, for clients that have to be pointed at
+    // the socket before it exists. `dir` is removed again on drop.
+    pub fn start_in(dir: &Path) -> io::Result<MockDaemon> {
+        MockDaemon::listen(dir.to_path_buf(), proto::PROTO_VERSION)
+    }
+
+    fn listen(dir: PathBuf, protocol: i32) -> io::Result<MockDaemon> {
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("spnav.sock");
+        let listener = UnixListener::bind(&path)?;
+        let shared = Arc::new(Shared::default());
+        {
+            let mut state = shared.lock();
+            state.protocol = protocol;
+            state.device = Some(DeviceInfo {
+                name: "3Dconnexion SpaceNavigator".into(),
+                path: "/dev/input/event0".into(),
+                vendor_id: 0x046d,
+                product_id: 0xc626,
+                devtype: 0,
+                num_axes: 6,
+                num_buttons: 2,
+            });
+        }
+        let acceptor = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("spacenav-mock".into())
+                .spawn(move || accept(listener, shared))?
+        };
+        Ok(MockDaemon {
+            dir,
+            path,
+            shared,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    // The socket clients should connect to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Number of clients currently connected
+    pub fn client_count(&self) -> usize {
+        self.shared.lock().clients.len()
+    }
+
+    // Blocks until `n` clients have connected in total, or `timeout` passes
+    pub fn wait_for_clients(&self, n: usize, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        while state.accepted < n {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = match self.shared.changed.wait_timeout(state, deadline - now) {
+                Ok((state, _)) => state,
+                Err(e) => e.into_inner().0,
+            };
+        }
+        true
+    }
+
+    // What device queries answer with; None makes them fail
+    pub fn set_device(&self, device: Option<DeviceInfo>) {
+        self.shared.lock().device = device;
+    }
+
+    // An event to send each new client before it hears back about the
+    // protocol, as if the device moved while it was connecting
+    pub fn set_greeting(&self, event: Option<Event>) {
+        self.shared.lock().greeting = event;
+    }
+
+    // Names connected clients registered, in the order they connected
+    pub fn client_names(&self) -> Vec<Option<String>> {
+        let state = self.shared.lock();
+        state
+            .clients
+            .iter()
+            .map(|client| client.name.clone())
+            .collect()
+    }
+
+    // How many times a client asked for the config to be saved
+    pub fn save_count(&self) -> usize {
+        self.shared.lock().config.saves
+    }
+
+    // Sends `event` to every connected client
+    pub fn send(&self, event: &Event) {
+        self.send_raw(proto::encode(event));
+    }
+
+    // Sends an arbitrary packet, e.g. one with an event type clients don't know
+    pub fn send_raw(&self, packet: [i32; 8]) {
+        deliver(&mut self.shared.lock(), None, &proto::to_bytes(&packet));
+    }
+
+    // Holds back everything sent, including replies to requests, until
+    // `resume` is called
+    pub fn stall(&self) {
+        self.shared.lock().stalled = true;
+    }
+
+    pub fn resume(&self) {
+        let mut state = self.shared.lock();
+        state.stalled = false;
+        for (target, bytes) in std::mem::take(&mut state.backlog) {
+            deliver(&mut state, target, &bytes);
+        }
+    }
+
+    // Hangs up on every connected client, as a daemon restart would
+    pub fn drop_clients(&self) {
+        for client in self.shared.lock().clients.drain(..) {
+            let _ = client.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for MockDaemon {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        // Wake the acceptor up so it notices the shutdown
+        let _ = UnixStream::connect(&self.path);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        self.drop_clients();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn accept(listener: UnixListener, shared: Arc<Shared>) {
+    for stream in listener.incoming() {
+        let mut state = shared.lock();
+        if state.shutdown {
+            return;
+        }
+        let Ok(stream) = stream else { continue };
+        let Ok(reader) = stream.try_clone() else {
+            continue;
+        };
+        let id = state.next_id;
+        state.next_id += 1;
+        state.clients.push(MockClient {
+            id,
+            stream,
+            mask: proto::EVMASK_ALL,
+            name: None,
+            partial_name: Vec::new(),
+        });
+        state.accepted += 1;
+        if let Some(event) = &state.greeting {
+            let bytes = proto::to_bytes(&proto::encode(event));
+            deliver(&mut state, Some(id), &bytes);
+        }
+        shared.changed.notify_all();
+        let shared = shared.clone();
+        let _ = thread::Builder::new()
+            .name(format!("spacenav-mock-client-{}", id))
+            .spawn(move || serve(reader, id, shared));
+    }
+}
+
+// Reads what one client sends until it goes away
+fn serve(mut stream: UnixStream, id: usize, shared: Arc<Shared>) {
+    let mut word = [0u8; 4];
+    if stream.read_exact(&mut word).is_err() {
+        return;
+    }
+    let cmd = i32::from_ne_bytes(word);
+    let mut proto = 0;
+    {
+        let mut state = shared.lock();
+        if state.protocol >= 1 && cmd & !0xff == proto::REQ_TAG | proto::REQ_CHANGE_PROTO {
+            proto = (cmd & 0xff).min(state.protocol);
+            let reply = proto::REQ_TAG | proto::REQ_CHANGE_PROTO | proto;
+            deliver(&mut state, Some(id), &reply.to_ne_bytes());
+        }
+    }
+    if proto == 0 {
+        // Old clients only ever send a float sensitivity, which we ignore
+        while stream.read_exact(&mut word).is_ok() {}
+        return;
+    }
+    let mut buf = [0u8; PACKET_SIZE];
+    while stream.read_exact(&mut buf).is_ok() {
+        let request = proto::parse(&buf);
+        let mut state = shared.lock();
+        for packet in respond(&mut state, id, &request) {
+            deliver(&mut state, Some(id), &proto::to_bytes(&packet));
+        }
+    }
+}
+
+fn respond(state: &mut State, id: usize, request: &Packet) -> Vec<Packet> {
+    let req = proto::request_type(request);
+    let q = &request[1..];
+    let reply = |data: [i32; 7]| vec![proto::request(req, data)];
+    let ok = reply([0; 7]);
+    let failed = reply([0, 0, 0, 0, 0, 0, -1]);
+    let client = state.clients.iter_mut().find(|client| client.id == id);
+    let cfg = &mut state.config;
+    let device = state.device.as_ref();
+    match req {
+        proto::REQ_SET_NAME | proto::REQ_SET_EVMASK | proto::REQ_GET_EVMASK if client.is_none() => {
+            failed
+        }
+        proto::REQ_SET_NAME => {
+            let client = client.unwrap();
+            if proto::decode_string(request, &mut client.partial_name) {
+                // Answered once the last packet is in
+                return Vec::new();
+            }
+            let name = std::mem::take(&mut client.partial_name);
+            client.name = Some(String::from_utf8_lossy(&name).into_owned());
+            ok
+        }
+        proto::REQ_SET_EVMASK => {
+            client.unwrap().mask = q[0];
+            ok
+        }
+        proto::REQ_GET_EVMASK => reply([client.unwrap().mask, 0, 0, 0, 0, 0, 0]),
+        proto::REQ_DEV_NAME
+        | proto::REQ_DEV_PATH
+        | proto::REQ_DEV_NAXES
+        | proto::REQ_DEV_NBUTTONS
+        | proto::REQ_DEV_USBID
+        | proto::REQ_DEV_TYPE
+            if device.is_none() =>
+        {
+            failed
+        }
+        proto::REQ_DEV_NAME => proto::encode_string(req, device.unwrap().name.as_bytes()),
+        proto::REQ_DEV_PATH => proto::encode_string(req, device.unwrap().path.as_bytes()),
+        proto::REQ_DEV_NAXES => reply([device.unwrap().num_axes as i32, 0, 0, 0, 0, 0, 0]),
+        proto::REQ_DEV_NBUTTONS => reply([device.unwrap().num_buttons as i32, 0, 0, 0, 0, 0, 0]),
+        proto::REQ_DEV_USBID => {
+            let device = device.unwrap();
+            let (vid, pid) = (device.vendor_id as i32, device.product_id as i32);
+            reply([vid, pid, 0, 0, 0, 0, 0])
+        }
+        proto::REQ_DEV_TYPE => reply([device.unwrap().devtype, 0, 0, 0, 0, 0, 0]),
+        proto::REQ_SCFG_SENS => {
+            cfg.sens = f32::from_bits(q[0] as u32);
+            ok
+        }
+        proto::REQ_GCFG_SENS => reply([cfg.sens.to_bits() as i32, 0, 0, 0, 0, 0, 0]),
+        proto::REQ_SCFG_SENS_AXIS => {
+            cfg.axis_sens = std::array::from_fn(|i| f32::from_bits(q[i] as u32));
+            ok
+        }
+        proto::REQ_GCFG_SENS_AXIS => {
+            let mut data = [0; 7];
+            for (d, s) in data.iter_mut().zip(cfg.axis_sens) {
+                *d = s.to_bits() as i32;
+            }
+            reply(data)
+        }
+        proto::REQ_SCFG_DEADZONE => {
+            cfg.deadzones.insert(q[0], q[1]);
+            ok
+        }
+        proto::REQ_GCFG_DEADZONE => {
+            let deadzone = cfg.deadzones.get(&q[0]).copied().unwrap_or(0);
+            reply([q[0], deadzone, 0, 0, 0, 0, 0])
+        }
+        proto::REQ_SCFG_INVERT => {
+            cfg.invert = std::array::from_fn(|i| q[i] != 0);
+            ok
+        }
+        proto::REQ_GCFG_INVERT => {
+            let mut data = [0; 7];
+            for (d, inv) in data.iter_mut().zip(cfg.invert) {
+                *d = inv as i32;
+            }
+            reply(data)
+        }
+        proto::REQ_SCFG_AXISMAP => {
+            cfg.axis_map.insert(q[0], q[1]);
+            ok
+        }
+        proto::REQ_GCFG_AXISMAP => {
+            let axis = cfg.axis_map.get(&q[0]).copied().unwrap_or(-1);
+            reply([q[0], axis, 0, 0, 0, 0, 0])
+        }
+        proto::REQ_SCFG_SWAPYZ => {
+            cfg.swap_yz = q[0] != 0;
+            ok
+        }
+        proto::REQ_GCFG_SWAPYZ => reply([cfg.swap_yz as i32, 0, 0, 0, 0, 0, 0]),
+        proto::REQ_CFG_SAVE => {
+            cfg.saves += 1;
+            ok
+        }
+        _ => failed,
+    }
+}
+
+// Writes to one client or all of them, forgetting the ones that have gone away.
+// Broadcasts are single events, which skip clients that masked them out.
+fn deliver(state: &mut State, target: Option<usize>, bytes: &[u8]) {
+    if state.stalled {
+        state.backlog.push((target, bytes.to_vec()));
+        return;
+    }
+    state.clients.retain(|client| {
+        let skip = match target {
+            Some(id) => id != client.id,
+            None => !proto::in_mask(&proto::parse(bytes), client.mask),
+        };
+        skip || (&client.stream).write_all(bytes).is_ok()
+    });
+}
+
+#[cfg(feature = "x11")]
+pub use magellan::FakeMagellanDriver;
+
+// Plays the 3Dconnexion driver's part of the Magellan X11 protocol on a real
+// X server, e.g. Xvfb
+#[cfg(feature = "x11")]
+mod magellan {
+    use super::*;
+    use crate::x11::{self, Atoms};
+    use std::os::unix::io::AsRawFd;
+    use x11rb::connection::Connection as _;
+    use x11rb::protocol::xproto::{
+        AtomEnum, ClientMessageEvent, ConnectionExt as _, CreateWindowAux, EventMask, PropMode,
+        Window, WindowClass,
+    };
+    use x11rb::rust_connection::RustConnection;
+    use x11rb::wrapper::ConnectionExt as _;
+
+    #[derive(Debug)]
+    pub struct FakeMagellanDriver {
+        conn: RustConnection,
+        atoms: Atoms,
+    }
+
+    impl FakeMagellanDriver {
+        // Creates the driver window and advertises it on the root window
+        pub fn start(display: Option<&str>) -> Result<FakeMagellanDriver> {
+            let op = Operation::Open;
+            let conn_err = |e| x11::connection_error(op, e);
+            let (conn, screen) = x11rb::connect(display).map_err(x11::connect_error)?;
+            let root = conn.setup().roots[screen].root;
+            let atoms = Atoms::intern(&conn)?;
+            let window = conn.generate_id().map_err(|e| x11::reply_error(op, e))?;
+            conn.create_window(
+                0,
+                window,
+                root,
+                0,
+                0,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_ONLY,
+                x11rb::COPY_FROM_PARENT,
+                &CreateWindowAux::new(),
+            )
+            .map_err(conn_err)?;
+            conn.change_property8(
+                PropMode::REPLACE,
+                window,
+                AtomEnum::WM_NAME,
+                AtomEnum::STRING,
+                x11::DRIVER_WINDOW_NAME,
+            )
+            .map_err(conn_err)?;
+            conn.change_property32(
+                PropMode::REPLACE,
+                root,
+                atoms.command,
+                AtomEnum::WINDOW,
+                &[window],
+            )
+            .map_err(conn_err)?;
+            conn.flush().map_err(conn_err)?;
+            Ok(FakeMagellanDriver { conn, atoms })
+        }
+
+        // Waits for a client to register the window it wants events sent to
+        pub fn wait_for_client(&self, timeout: Duration) -> Result<Option<Window>> {
+            let op = Operation::WaitEvent;
+            let deadline = Instant::now() + timeout;
+            loop {
+                while let Some(event) = self
+                    .conn
+                    .poll_for_event()
+                    .map_err(|e| x11::connection_error(op, e))?
+                {
+                    let x11rb::protocol::Event::ClientMessage(message) = event else {
+                        continue;
+                    };
+                    let data = message.data.as_data16();
+                    if message.type_ == self.atoms.command && data[2] == x11::CMD_APP_WINDOW {
+                        return Ok(Some((data[0] as u32) << 16 | data[1] as u32));
+                    }
+                }
+                let fd = self.conn.stream().as_raw_fd();
+                let ready = sys::poll_readable(fd, None, Some(deadline))
+                    .map_err(|e| Error::from_io(op, &e))?;
+                if ready == sys::Readiness::TimedOut {
+                    return Ok(None);
+                }
+            }
+        }
+
+        // Sends a motion or button event to `window`; other events have no
+        // Magellan equivalent and are ignored
+        pub fn send(&self, window: Window, event: &Event) -> Result<()> {
+            let op = Operation::Open;
+            let (type_, data) = match event {
+                Event::Motion(m) => {
+                    let axes = [m.x, m.y, m.z, m.rx, m.ry, m.rz, m.period as i32];
+                    let mut data = [0u16; 10];
+                    for (d, v) in data[2..9].iter_mut().zip(axes) {
+                        *d = v as i16 as u16;
+                    }
+                    (self.atoms.motion, data)
+                }
+                Event::Button(b) => {
+                    let type_ = if b.press {
+                        self.atoms.button_press
+                    } else {
+                        self.atoms.button_release
+                    };
+                    let mut data = [0u16; 10];
+                    data[2] = b.bnum as u16;
+                    (type_, data)
+                }
+                _ => return Ok(()),
+            };
+            let message = ClientMessageEvent::new(16, window, type_, data);
+            self.conn
+                .send_event(false, window, EventMask::NO_EVENT, message)
+                .map_err(|e| x11::connection_error(op, e))?;
+            self.conn.flush().map_err(|e| x11::connection_error(op, e))
+        }
+    }
+}
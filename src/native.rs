@@ -0,0 +1,348 @@
+// Pure Rust client for the spacenavd AF_UNIX protocol. Unlike libspnav there is
+// no global state, so any number of clients can be open at once.
+use super::*;
+use crate::proto::{self, Packet, PACKET_SIZE};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+pub const DEFAULT_SOCKET: &str = "/var/run/spnav.sock";
+
+// How long to wait for the daemon to answer a protocol change or request
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(500);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Where spacenavd is listening: $SPNAV_SOCKET if set, then spnav.sock in
+// $XDG_RUNTIME_DIR if there is one, otherwise the daemon's default
+pub fn socket_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("SPNAV_SOCKET") {
+        return PathBuf::from(path);
+    }
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(|dir| Path::new(&dir).join("spnav.sock"))
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET))
+}
+
+#[derive(Debug)]
+pub struct Client {
+    stream: UnixStream,
+    inbox: Mutex<Inbox>,
+    // Held for a whole request so concurrent requests can't take each other's
+    // responses
+    requests: Mutex<()>,
+    // Signalled when a response is queued, in case another thread read it
+    responded: CancelHandle,
+    proto: i32,
+    // Event types to keep, as proto::EVMASK_* bits
+    mask: AtomicI32,
+}
+
+// Packets read off the socket but not handed out yet
+#[derive(Debug, Default)]
+struct Inbox {
+    partial: Vec<u8>,
+    queue: VecDeque<Packet>,
+    responses: VecDeque<Packet>,
+    hung_up: bool,
+}
+
+impl Client {
+    pub fn open() -> Result<Client> {
+        Client::connect(socket_path())
+    }
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Client> {
+        let stream = UnixStream::connect(path).map_err(|e| Error::from_io(Operation::Open, &e))?;
+        stream
+            .set_nonblocking(true)
+            .map_err(|e| Error::from_io(Operation::Open, &e))?;
+        Client::handshake(stream, None)
+    }
+    // Like connect, but fails with ETIMEDOUT rather than waiting past
+    // `timeout` for the daemon to accept us. A timeout too large to add to
+    // the current time means no deadline.
+    pub fn connect_timeout<P: AsRef<Path>>(path: P, timeout: Duration) -> Result<Client> {
+        let deadline = Instant::now().checked_add(timeout);
+        let stream = sys::connect_unix(path.as_ref(), deadline)
+            .map_err(|e| Error::from_io(Operation::Open, &e))?;
+        Client::handshake(stream, deadline)
+    }
+    pub fn fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+    // Returns the next pending event without blocking
+    pub fn poll(&self) -> Result<Option<Event>> {
+        match self.next_packet(Operation::PollEvent)? {
+            Some(packet) => proto::decode(&packet).map(Some),
+            None => Ok(None),
+        }
+    }
+    // Blocks until the daemon sends an event
+    pub fn wait(&self) -> Result<Event> {
+        loop {
+            if let Some(packet) = self.next_packet(Operation::WaitEvent)? {
+                return proto::decode(&packet);
+            }
+            sys::poll_readable(self.fd(), None, None)
+                .map_err(|e| Error::from_io(Operation::WaitEvent, &e))?;
+        }
+    }
+    // Drops pending events of type `t`, returning how many were removed
+    pub fn remove_events(&self, t: EventType) -> Result<usize> {
+        let mut inbox = self.lock();
+        self.fill(&mut inbox, Operation::PollEvent)?;
+        let before = inbox.queue.len();
+        inbox.queue.retain(|packet| !proto::matches(packet, t));
+        Ok(before - inbox.queue.len())
+    }
+    // Only events of the given types are handed out from now on; pending ones
+    // of other types are dropped. Daemons speaking protocol v1 are asked to
+    // stop sending the rest, older ones are filtered here.
+    pub fn set_event_mask(&self, types: &[EventType]) -> Result<()> {
+        self.update_mask(|| proto::evmask(types))
+    }
+    // set_event_mask with the mask in proto::EVMASK_* bits, worked out while
+    // holding the request lock so concurrent updates can't reach the daemon
+    // out of order
+    pub(crate) fn update_mask(&self, mask: impl FnOnce() -> i32) -> Result<()> {
+        let _guard = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        let mask = mask();
+        self.mask.store(mask, Ordering::Relaxed);
+        self.lock()
+            .queue
+            .retain(|packet| proto::in_mask(packet, mask));
+        if self.proto < 1 {
+            return Ok(());
+        }
+        match self.request(
+            Operation::EventMask,
+            proto::REQ_SET_EVMASK,
+            [mask, 0, 0, 0, 0, 0, 0],
+        ) {
+            // We filter anyway, so a daemon that won't is no reason to fail
+            Ok(_) | Err(Error::Rejected { .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+    // Tells the daemon what to call this client in its logs
+    pub fn set_client_name(&self, name: &str) -> Result<()> {
+        let op = Operation::ClientName;
+        let _guard = self.begin_request(op)?;
+        for packet in proto::encode_string(proto::REQ_SET_NAME, name.as_bytes()) {
+            self.send(op, &proto::to_bytes(&packet))?;
+        }
+        self.status(op, self.response(op, proto::REQ_SET_NAME)?)
+            .map(drop)
+    }
+    // The protocol version agreed on when connecting; 0 for daemons from before
+    // spacenavd 1.0, which answer no requests
+    pub fn protocol_version(&self) -> i32 {
+        self.proto
+    }
+    pub fn device_info(&self) -> Result<DeviceInfo> {
+        let op = Operation::DeviceInfo;
+        let _guard = self.begin_request(op)?;
+        let usbid = self.request(op, proto::REQ_DEV_USBID, [0; 7])?;
+        Ok(DeviceInfo {
+            name: self.request_string(op, proto::REQ_DEV_NAME)?,
+            path: self.request_string(op, proto::REQ_DEV_PATH)?,
+            vendor_id: usbid[1] as u16,
+            product_id: usbid[2] as u16,
+            devtype: self.request(op, proto::REQ_DEV_TYPE, [0; 7])?[1],
+            num_axes: self.request(op, proto::REQ_DEV_NAXES, [0; 7])?[1] as usize,
+            num_buttons: self.request(op, proto::REQ_DEV_NBUTTONS, [0; 7])?[1] as usize,
+        })
+    }
+    // Makes a single protocol v1 request, returning the daemon's response
+    pub(crate) fn call(&self, op: Operation, req: i32, data: [i32; 7]) -> Result<Packet> {
+        let _guard = self.begin_request(op)?;
+        self.request(op, req, data)
+    }
+
+    fn handshake(stream: UnixStream, deadline: Option<Instant>) -> Result<Client> {
+        let mut client = Client {
+            stream,
+            inbox: Mutex::default(),
+            requests: Mutex::default(),
+            responded: CancelHandle::new()?,
+            proto: 0,
+            mask: AtomicI32::new(proto::EVMASK_ALL),
+        };
+        client.proto = client.negotiate(deadline)?;
+        Ok(client)
+    }
+    fn lock(&self) -> MutexGuard<'_, Inbox> {
+        // The inbox is always left consistent, so a panic elsewhere doesn't matter
+        self.inbox.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    fn next_packet(&self, op: Operation) -> Result<Option<Packet>> {
+        let mut inbox = self.lock();
+        if inbox.queue.is_empty() {
+            self.fill(&mut inbox, op)?;
+        }
+        match inbox.queue.pop_front() {
+            Some(packet) => Ok(Some(packet)),
+            None if inbox.hung_up => Err(Error::Disconnected { op }),
+            None => Ok(None),
+        }
+    }
+    // Reads everything the socket has available without blocking
+    fn fill(&self, inbox: &mut Inbox, op: Operation) -> Result<()> {
+        let mut buf = [0u8; PACKET_SIZE * 16];
+        while !inbox.hung_up {
+            match (&self.stream).read(&mut buf) {
+                Ok(0) => inbox.hung_up = true,
+                Ok(n) => {
+                    inbox.partial.extend_from_slice(&buf[..n]);
+                    let whole = inbox.partial.len() / PACKET_SIZE * PACKET_SIZE;
+                    let packets: Vec<Packet> = inbox.partial[..whole]
+                        .chunks_exact(PACKET_SIZE)
+                        .map(proto::parse)
+                        .collect();
+                    inbox.partial.drain(..whole);
+                    let mask = self.mask.load(Ordering::Relaxed);
+                    for packet in packets {
+                        if proto::is_response(&packet) {
+                            inbox.responses.push_back(packet);
+                            self.responded.cancel();
+                        } else if proto::in_mask(&packet, mask) {
+                            inbox.queue.push_back(packet);
+                        }
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::from_io(op, &e)),
+            }
+        }
+        Ok(())
+    }
+    fn send(&self, op: Operation, mut bytes: &[u8]) -> Result<()> {
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        while !bytes.is_empty() {
+            match (&self.stream).write(bytes) {
+                Ok(n) => bytes = &bytes[n..],
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if !sys::poll_writable(self.fd(), Some(deadline))
+                        .map_err(|e| Error::from_io(op, &e))?
+                    {
+                        return Err(Error::timed_out(op));
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::from_io(op, &e)),
+            }
+        }
+        Ok(())
+    }
+
+    // Asks for protocol v1, returning the version the daemon agreed to. An
+    // earlier `deadline` cuts the wait for an answer short.
+    fn negotiate(&self, deadline: Option<Instant>) -> Result<i32> {
+        let op = Operation::Open;
+        let cmd = proto::REQ_TAG | proto::REQ_CHANGE_PROTO | proto::PROTO_VERSION;
+        self.send(op, &cmd.to_ne_bytes())?;
+        let handshake = Instant::now() + HANDSHAKE_TIMEOUT;
+        let deadline = deadline.map_or(handshake, |d| d.min(handshake));
+        let mut packet = [0u8; PACKET_SIZE];
+        loop {
+            let mut len = self.read_until(op, &mut packet[..4], deadline)?;
+            if len == 4 {
+                let reply = i32::from_ne_bytes([packet[0], packet[1], packet[2], packet[3]]);
+                if reply & !0xff == proto::REQ_TAG | proto::REQ_CHANGE_PROTO {
+                    return Ok(reply & 0xff);
+                }
+                // An event that got in ahead of the reply; keep it and wait on
+                len += self.read_until(op, &mut packet[4..], deadline)?;
+                if len == PACKET_SIZE {
+                    self.lock().queue.push_back(proto::parse(&packet));
+                    continue;
+                }
+            }
+            // No reply in time: an old daemon that ignored us, and whatever
+            // came in is the start of an event
+            self.lock().partial.extend_from_slice(&packet[..len]);
+            return Ok(0);
+        }
+    }
+    // Fills `buf`, stopping early at `deadline`; returns how much was read
+    fn read_until(&self, op: Operation, buf: &mut [u8], deadline: Instant) -> Result<usize> {
+        let mut len = 0;
+        while len < buf.len() {
+            match (&self.stream).read(&mut buf[len..]) {
+                Ok(0) => return Err(Error::Disconnected { op }),
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    match sys::poll_readable(self.fd(), None, Some(deadline))
+                        .map_err(|e| Error::from_io(op, &e))?
+                    {
+                        sys::Readiness::TimedOut => break,
+                        _ => continue,
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::from_io(op, &e)),
+            }
+        }
+        Ok(len)
+    }
+    fn begin_request(&self, op: Operation) -> Result<MutexGuard<'_, ()>> {
+        if self.proto < 1 {
+            return Err(Error::Unsupported { op });
+        }
+        Ok(self.requests.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+    // Sends a request and waits for its response. Callers hold begin_request.
+    fn request(&self, op: Operation, req: i32, data: [i32; 7]) -> Result<Packet> {
+        self.send(op, &proto::to_bytes(&proto::request(req, data)))?;
+        self.status(op, self.response(op, req)?)
+    }
+    fn status(&self, op: Operation, response: Packet) -> Result<Packet> {
+        match response[7] {
+            status if status < 0 => Err(Error::Rejected { op, status }),
+            _ => Ok(response),
+        }
+    }
+    fn request_string(&self, op: Operation, req: i32) -> Result<String> {
+        self.send(op, &proto::to_bytes(&proto::request(req, [0; 7])))?;
+        let mut bytes = Vec::new();
+        loop {
+            let response = self.status(op, self.response(op, req)?)?;
+            if !proto::decode_string(&response, &mut bytes) {
+                return Ok(String::from_utf8_lossy(&bytes).into_owned());
+            }
+        }
+    }
+    // Waits for the next response to `req`, queueing events that arrive first
+    fn response(&self, op: Operation, req: i32) -> Result<Packet> {
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        loop {
+            // Re-armed before looking, so a response queued by another thread
+            // after that still wakes the poll below
+            self.responded.reset();
+            {
+                let mut inbox = self.lock();
+                self.fill(&mut inbox, op)?;
+                if let Some(i) = inbox
+                    .responses
+                    .iter()
+                    .position(|packet| proto::request_type(packet) == req)
+                {
+                    return Ok(inbox.responses.remove(i).expect("index in range"));
+                }
+                if inbox.hung_up {
+                    return Err(Error::Disconnected { op });
+                }
+            }
+            let ready = sys::poll_readable(self.fd(), Some(self.responded.fd()), Some(deadline))
+                .map_err(|e| Error::from_io(op, &e))?;
+            if ready == sys::Readiness::TimedOut {
+                return Err(Error::timed_out(op));
+            }
+        }
+    }
+}
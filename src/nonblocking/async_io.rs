@@ -0,0 +1,59 @@
+use super::*;
+use ::async_io::Async;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// A Connection registered with the async-io reactor, usable from smol,
+// async-std or any other executor.
+#[derive(Debug)]
+pub struct AsyncConnection {
+    inner: Async<Connection>,
+    // The Stream has ended
+    done: bool,
+}
+
+impl AsyncConnection {
+    pub fn new(conn: Connection) -> Result<AsyncConnection> {
+        let inner = Async::new(conn).map_err(|e| Error::from_io(Operation::Open, &e))?;
+        Ok(AsyncConnection { inner, done: false })
+    }
+    pub fn get_ref(&self) -> &Connection {
+        self.inner.get_ref()
+    }
+    pub fn into_inner(self) -> Result<Connection> {
+        self.inner
+            .into_inner()
+            .map_err(|e| Error::from_io(Operation::Close, &e))
+    }
+    pub async fn next_event(&self) -> Result<Event> {
+        self.inner
+            .read_with(read_event)
+            .await
+            .map_err(|e| Error::from_io(Operation::WaitEvent, &e))?
+    }
+}
+
+// Yields events until the connection fails, skipping events of unknown type,
+// and stays ended after that; use next_event to see the error
+impl Stream for AsyncConnection {
+    type Item = Event;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        let next = loop {
+            if let Ok(next) = read_stream_event(this.inner.get_ref()) {
+                break next;
+            }
+            match this.inner.poll_readable(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(_)) => break None,
+                Poll::Pending => return Poll::Pending,
+            }
+        };
+        this.done = next.is_none();
+        Poll::Ready(next)
+    }
+}
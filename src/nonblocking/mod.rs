@@ -0,0 +1,31 @@
+// Async adapters that wait for readiness on `Connection::fd` and then read
+// through the regular non-blocking poll.
+use super::*;
+use std::io;
+
+#[cfg(feature = "async-io")]
+pub mod async_io;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+
+// Reads the next event, reporting "nothing pending" as WouldBlock so it slots
+// into the runtimes' try_io helpers
+fn read_event(conn: &Connection) -> io::Result<Result<Event>> {
+    match conn.try_poll() {
+        Ok(Some(event)) => Ok(Ok(event)),
+        Ok(None) => Err(io::ErrorKind::WouldBlock.into()),
+        Err(e) => Ok(Err(e)),
+    }
+}
+
+// What the Streams yield: the next event, skipping ones of unknown type, or
+// None once the connection has failed. WouldBlock while nothing is pending.
+fn read_stream_event(conn: &Connection) -> io::Result<Option<Event>> {
+    loop {
+        match read_event(conn)? {
+            Ok(event) => return Ok(Some(event)),
+            Err(Error::UnknownEvent { .. }) => {}
+            Err(_) => return Ok(None),
+        }
+    }
+}
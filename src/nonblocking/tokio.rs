@@ -0,0 +1,70 @@
+use super::*;
+use ::tokio::io::unix::AsyncFd;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// A Connection registered with the tokio reactor. Must be created from within
+// a tokio runtime.
+#[derive(Debug)]
+pub struct AsyncConnection {
+    inner: AsyncFd<Connection>,
+    // The Stream has ended
+    done: bool,
+}
+
+impl AsyncConnection {
+    pub fn new(conn: Connection) -> Result<AsyncConnection> {
+        let inner = AsyncFd::new(conn).map_err(|e| Error::from_io(Operation::Open, &e))?;
+        Ok(AsyncConnection { inner, done: false })
+    }
+    pub fn get_ref(&self) -> &Connection {
+        self.inner.get_ref()
+    }
+    pub fn into_inner(self) -> Connection {
+        self.inner.into_inner()
+    }
+    pub async fn next_event(&self) -> Result<Event> {
+        // The native client may already hold events the fd won't signal again
+        if let Ok(event) = read_event(self.inner.get_ref()) {
+            return event;
+        }
+        loop {
+            let mut guard = self
+                .inner
+                .readable()
+                .await
+                .map_err(|e| Error::from_io(Operation::WaitEvent, &e))?;
+            if let Ok(event) = guard.try_io(|inner| read_event(inner.get_ref())) {
+                return event.map_err(|e| Error::from_io(Operation::WaitEvent, &e))?;
+            }
+        }
+    }
+}
+
+// Yields events until the connection fails, skipping events of unknown type,
+// and stays ended after that; use next_event to see the error
+impl Stream for AsyncConnection {
+    type Item = Event;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        let next = match read_stream_event(this.inner.get_ref()) {
+            Ok(next) => next,
+            Err(_) => loop {
+                let mut guard = match this.inner.poll_read_ready(cx) {
+                    Poll::Ready(Ok(guard)) => guard,
+                    Poll::Ready(Err(_)) => break None,
+                    Poll::Pending => return Poll::Pending,
+                };
+                if let Ok(next) = guard.try_io(|inner| read_stream_event(inner.get_ref())) {
+                    break next.ok().flatten();
+                }
+            },
+        };
+        this.done = next.is_none();
+        Poll::Ready(next)
+    }
+}
@@ -0,0 +1,185 @@
+// Motion as f32 in [-1, 1], independent of the device model. Raw counts are
+// divided by the device's full-scale value, taken either from a table of
+// known devices or from the largest values seen so far.
+use super::*;
+
+// Translation and rotation counts at full deflection
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FullScale {
+    pub translation: f32,
+    pub rotation: f32,
+}
+
+impl FullScale {
+    pub fn new(translation: f32, rotation: f32) -> FullScale {
+        FullScale {
+            translation,
+            rotation,
+        }
+    }
+}
+
+// What a SpaceNavigator reports, for when the device isn't known
+impl Default for FullScale {
+    fn default() -> FullScale {
+        DeviceModel::SpaceNavigator.full_scale()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DeviceModel {
+    SpaceNavigator,
+    SpaceMouseCompact,
+    SpaceMousePro,
+    SpaceMouseEnterprise,
+    SpaceMouseWireless,
+}
+
+const LOGITECH: u16 = 0x046d;
+const THREEDCONNEXION: u16 = 0x256f;
+
+// (vendor id, product id, model); wireless models show up once over the cable
+// and once through the receiver
+const DEVICES: &[(u16, u16, DeviceModel)] = &[
+    (LOGITECH, 0xc626, DeviceModel::SpaceNavigator),
+    (LOGITECH, 0xc628, DeviceModel::SpaceNavigator),
+    (THREEDCONNEXION, 0xc635, DeviceModel::SpaceMouseCompact),
+    (LOGITECH, 0xc62b, DeviceModel::SpaceMousePro),
+    (THREEDCONNEXION, 0xc631, DeviceModel::SpaceMousePro),
+    (THREEDCONNEXION, 0xc632, DeviceModel::SpaceMousePro),
+    (THREEDCONNEXION, 0xc633, DeviceModel::SpaceMouseEnterprise),
+    (THREEDCONNEXION, 0xc62e, DeviceModel::SpaceMouseWireless),
+    (THREEDCONNEXION, 0xc62f, DeviceModel::SpaceMouseWireless),
+];
+
+impl DeviceModel {
+    pub fn from_ids(vendor_id: u16, product_id: u16) -> Option<DeviceModel> {
+        DEVICES
+            .iter()
+            .find(|&&(v, p, _)| v == vendor_id && p == product_id)
+            .map(|&(_, _, model)| model)
+    }
+    pub fn from_info(info: &DeviceInfo) -> Option<DeviceModel> {
+        DeviceModel::from_ids(info.vendor_id, info.product_id)
+    }
+    // At the daemon's default sensitivity of 1.0. Other sensitivities scale
+    // the counts, so either scale these to match or use auto-calibration.
+    // Every model in the table declares -350..350 as the logical range of all
+    // six axes in its HID report descriptor, so they share one value; a model
+    // that differs gets its own arm here.
+    pub fn full_scale(self) -> FullScale {
+        match self {
+            DeviceModel::SpaceNavigator
+            | DeviceModel::SpaceMouseCompact
+            | DeviceModel::SpaceMousePro
+            | DeviceModel::SpaceMouseEnterprise
+            | DeviceModel::SpaceMouseWireless => FullScale::new(350.0, 350.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NormalizedMotion {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 3],
+    // Milliseconds since the previous motion event, as in MotionEvent
+    pub period: u32,
+}
+
+impl NormalizedMotion {
+    // Divides by the full-scale values, clamping to [-1, 1]
+    pub fn new(motion: &MotionEvent, full_scale: FullScale) -> NormalizedMotion {
+        let (t, r) = (full_scale.translation, full_scale.rotation);
+        NormalizedMotion::scaled(motion, [t, t, t, r, r, r])
+    }
+    pub fn t(&self) -> (f32, f32, f32) {
+        let [x, y, z] = self.translation;
+        (x, y, z)
+    }
+    pub fn r(&self) -> (f32, f32, f32) {
+        let [rx, ry, rz] = self.rotation;
+        (rx, ry, rz)
+    }
+
+    fn scaled(motion: &MotionEvent, full_scale: [f32; 6]) -> NormalizedMotion {
+        let axes = filter::axes(motion);
+        let v = [0, 1, 2, 3, 4, 5].map(|i| {
+            if full_scale[i] > 0.0 {
+                (axes[i] as f32 / full_scale[i]).clamp(-1.0, 1.0)
+            } else {
+                0.0
+            }
+        });
+        NormalizedMotion {
+            translation: [v[0], v[1], v[2]],
+            rotation: [v[3], v[4], v[5]],
+            period: motion.period,
+        }
+    }
+}
+
+// Where the full-scale values come from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Calibration {
+    Fixed(FullScale),
+    // Each axis' full scale is the largest magnitude seen on it, never less
+    // than `floor`, so the first small nudge doesn't read as full deflection
+    Auto { floor: f32 },
+}
+
+// Converts a stream of MotionEvents, keeping track of the observed maximums
+// in auto-calibration mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct Normalizer {
+    calibration: Calibration,
+    full_scale: [f32; 6],
+}
+
+impl Normalizer {
+    pub fn new(calibration: Calibration) -> Normalizer {
+        let full_scale = match calibration {
+            Calibration::Fixed(FullScale {
+                translation: t,
+                rotation: r,
+            }) => [t, t, t, r, r, r],
+            Calibration::Auto { floor } => [floor; 6],
+        };
+        Normalizer {
+            calibration,
+            full_scale,
+        }
+    }
+    // Uses the table for known devices and auto-calibration for the rest
+    pub fn for_device(info: &DeviceInfo) -> Normalizer {
+        match DeviceModel::from_info(info) {
+            Some(model) => Normalizer::new(Calibration::Fixed(model.full_scale())),
+            None => Normalizer::auto(),
+        }
+    }
+    // Auto-calibration starting from a floor of 100 counts
+    pub fn auto() -> Normalizer {
+        Normalizer::new(Calibration::Auto { floor: 100.0 })
+    }
+    pub fn calibration(&self) -> Calibration {
+        self.calibration
+    }
+    // The current divisor for each axis, x, y, z, rx, ry, rz
+    pub fn full_scale(&self) -> [f32; 6] {
+        self.full_scale
+    }
+    pub fn normalize(&mut self, motion: &MotionEvent) -> NormalizedMotion {
+        if let Calibration::Auto { .. } = self.calibration {
+            let axes = filter::axes(motion);
+            for (max, v) in self.full_scale.iter_mut().zip(axes) {
+                *max = max.max(v.unsigned_abs() as f32);
+            }
+        }
+        NormalizedMotion::scaled(motion, self.full_scale)
+    }
+    // Starts auto-calibration over, e.g. after the daemon's sensitivity changed
+    pub fn reset(&mut self) {
+        *self = Normalizer::new(self.calibration);
+    }
+}
@@ -0,0 +1,225 @@
+// Integrates MotionEvents into a rigid transform, for viewers that fly a
+// camera or move an object around with the device. Each event is treated as
+// a velocity held for its `period`: translation in units per second and
+// rotation in radians per second, per count, scaled by `scale`.
+use super::*;
+use std::ops::Mul;
+
+// Events after the device was left alone carry the whole idle time as their
+// period, which would otherwise turn into one big jump
+const DEFAULT_MAX_PERIOD: Duration = Duration::from_millis(100);
+
+// A unit quaternion, w + xi + yj + zk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub const IDENTITY: Quaternion = Quaternion {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    // A rotation by |v| radians about v
+    pub fn from_rotation_vector(v: [f64; 3]) -> Quaternion {
+        let angle = norm(v);
+        if angle == 0.0 {
+            return Quaternion::IDENTITY;
+        }
+        let s = (angle / 2.0).sin() / angle;
+        Quaternion {
+            w: (angle / 2.0).cos(),
+            x: v[0] * s,
+            y: v[1] * s,
+            z: v[2] * s,
+        }
+    }
+    pub fn conjugate(self) -> Quaternion {
+        Quaternion {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+    pub fn normalize(self) -> Quaternion {
+        let n = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        if n == 0.0 {
+            return Quaternion::IDENTITY;
+        }
+        Quaternion {
+            w: self.w / n,
+            x: self.x / n,
+            y: self.y / n,
+            z: self.z / n,
+        }
+    }
+    // Applies the rotation to a vector
+    pub fn rotate(self, v: [f64; 3]) -> [f64; 3] {
+        let p = Quaternion {
+            w: 0.0,
+            x: v[0],
+            y: v[1],
+            z: v[2],
+        };
+        let r = self * p * self.conjugate();
+        [r.x, r.y, r.z]
+    }
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Quaternion::IDENTITY
+    }
+}
+
+// Hamilton product: `a * b` rotates by b first, then by a
+impl Mul for Quaternion {
+    type Output = Quaternion;
+    fn mul(self, b: Quaternion) -> Quaternion {
+        let a = self;
+        Quaternion {
+            w: a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            x: a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            y: a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            z: a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Pose {
+    pub translation: [f64; 3],
+    pub rotation: Quaternion,
+}
+
+// Which axes the device's motion is along
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Frame {
+    // Fixed world axes, e.g. for moving an object in a scene
+    #[default]
+    World,
+    // The pose's own axes, e.g. for flying a camera: pushing forward moves
+    // whichever way it currently faces
+    Body,
+}
+
+#[derive(Debug, Clone)]
+pub struct PoseIntegrator {
+    pose: Pose,
+    frame: Frame,
+    translation_scale: f64,
+    rotation_scale: f64,
+    max_period: Duration,
+    bounds: Option<([f64; 3], [f64; 3])>,
+}
+
+impl Default for PoseIntegrator {
+    fn default() -> Self {
+        PoseIntegrator::new()
+    }
+}
+
+impl PoseIntegrator {
+    // Starts at the identity pose in world frame, with one unit or radian per
+    // second per count and periods capped at 100ms
+    pub fn new() -> PoseIntegrator {
+        PoseIntegrator {
+            pose: Pose::default(),
+            frame: Frame::default(),
+            translation_scale: 1.0,
+            rotation_scale: 1.0,
+            max_period: DEFAULT_MAX_PERIOD,
+            bounds: None,
+        }
+    }
+    pub fn frame(mut self, frame: Frame) -> Self {
+        self.frame = frame;
+        self
+    }
+    // Units per second, and radians per second, for one count of deflection
+    pub fn scale(mut self, translation: f64, rotation: f64) -> Self {
+        self.translation_scale = translation;
+        self.rotation_scale = rotation;
+        self
+    }
+    // The longest period a single event is integrated over
+    pub fn max_period(mut self, max_period: Duration) -> Self {
+        self.max_period = max_period;
+        self
+    }
+    // Keeps the translation within the box from `min` to `max`
+    pub fn bounds(mut self, min: [f64; 3], max: [f64; 3]) -> Self {
+        self.bounds = Some((min, max));
+        self.pose.translation = self.clamp(self.pose.translation);
+        self
+    }
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+    // Back to the identity pose
+    pub fn reset(&mut self) {
+        self.reset_to(Pose::default());
+    }
+    pub fn reset_to(&mut self, pose: Pose) {
+        self.pose = Pose {
+            translation: self.clamp(pose.translation),
+            rotation: pose.rotation.normalize(),
+        };
+    }
+    // Moves the pose by one event's worth of motion and returns it
+    pub fn update(&mut self, motion: &MotionEvent) -> Pose {
+        let dt = Duration::from_millis(motion.period.into())
+            .min(self.max_period)
+            .as_secs_f64();
+        let (x, y, z) = motion.t();
+        let (rx, ry, rz) = motion.r();
+        let step = |v: i32, scale: f64| v as f64 * scale * dt;
+        let t = [
+            step(x, self.translation_scale),
+            step(y, self.translation_scale),
+            step(z, self.translation_scale),
+        ];
+        let r = Quaternion::from_rotation_vector([
+            step(rx, self.rotation_scale),
+            step(ry, self.rotation_scale),
+            step(rz, self.rotation_scale),
+        ]);
+        let Pose {
+            translation,
+            rotation,
+        } = self.pose;
+        let (t, rotation) = match self.frame {
+            Frame::World => (t, r * rotation),
+            // Moves along the axes as they were at the start of the step
+            Frame::Body => (rotation.rotate(t), rotation * r),
+        };
+        self.pose = Pose {
+            translation: self.clamp([
+                translation[0] + t[0],
+                translation[1] + t[1],
+                translation[2] + t[2],
+            ]),
+            // Keeps rounding errors from building up
+            rotation: rotation.normalize(),
+        };
+        self.pose
+    }
+
+    fn clamp(&self, t: [f64; 3]) -> [f64; 3] {
+        match self.bounds {
+            Some((min, max)) => [0, 1, 2].map(|i| t[i].max(min[i]).min(max[i])),
+            None => t,
+        }
+    }
+}
+
+fn norm(v: [f64; 3]) -> f64 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
@@ -0,0 +1,234 @@
+// spacenavd AF_UNIX wire protocol. Every message in either direction is eight
+// native-endian ints.
+use super::*;
+
+pub(crate) const PACKET_SIZE: usize = 32;
+pub(crate) type Packet = [i32; 8];
+
+// Event types in data[0] of a packet sent by the daemon
+pub(crate) const UEV_MOTION: i32 = 0;
+pub(crate) const UEV_PRESS: i32 = 1;
+pub(crate) const UEV_RELEASE: i32 = 2;
+pub(crate) const UEV_DEV: i32 = 3;
+pub(crate) const UEV_CFG: i32 = 4;
+pub(crate) const UEV_RAWAXIS: i32 = 5;
+pub(crate) const UEV_RAWBUTTON: i32 = 6;
+
+// data[1] of a UEV_DEV packet
+const DEV_ADD: i32 = 0;
+
+// Protocol v1 requests carry REQ_TAG | request in data[0], so responses can be
+// told apart from events. The rest of the packet is the request's data, with
+// the daemon's status (negative on failure) in data[7] of the response.
+pub(crate) const REQ_TAG: i32 = 0x7faa0000;
+const REQ_TAG_MASK: i32 = 0xffff0000u32 as i32;
+
+// Sent as a single int right after connecting. Daemons that speak v1 answer
+// with a single int carrying the version they settled on; older ones stay quiet.
+pub(crate) const REQ_CHANGE_PROTO: i32 = 0x5500;
+pub(crate) const PROTO_VERSION: i32 = 1;
+
+pub(crate) const REQ_SET_NAME: i32 = 0x1000;
+pub(crate) const REQ_SET_EVMASK: i32 = 0x1003;
+#[cfg(any(test, feature = "mock"))]
+pub(crate) const REQ_GET_EVMASK: i32 = 0x1004;
+
+// Bits of the event mask sent with REQ_SET_EVMASK, one per event type
+pub(crate) const EVMASK_MOTION: i32 = 0x01;
+pub(crate) const EVMASK_BUTTON: i32 = 0x02;
+pub(crate) const EVMASK_DEV: i32 = 0x04;
+pub(crate) const EVMASK_CFG: i32 = 0x08;
+pub(crate) const EVMASK_RAWAXIS: i32 = 0x10;
+pub(crate) const EVMASK_RAWBUTTON: i32 = 0x20;
+pub(crate) const EVMASK_ALL: i32 = 0x3f;
+
+pub(crate) const REQ_DEV_NAME: i32 = 0x2000;
+pub(crate) const REQ_DEV_PATH: i32 = 0x2001;
+pub(crate) const REQ_DEV_NAXES: i32 = 0x2002;
+pub(crate) const REQ_DEV_NBUTTONS: i32 = 0x2003;
+pub(crate) const REQ_DEV_USBID: i32 = 0x2004;
+pub(crate) const REQ_DEV_TYPE: i32 = 0x2005;
+
+pub(crate) const REQ_SCFG_SENS: i32 = 0x3000;
+pub(crate) const REQ_GCFG_SENS: i32 = 0x3001;
+pub(crate) const REQ_SCFG_SENS_AXIS: i32 = 0x3002;
+pub(crate) const REQ_GCFG_SENS_AXIS: i32 = 0x3003;
+pub(crate) const REQ_SCFG_DEADZONE: i32 = 0x3004;
+pub(crate) const REQ_GCFG_DEADZONE: i32 = 0x3005;
+pub(crate) const REQ_SCFG_INVERT: i32 = 0x3006;
+pub(crate) const REQ_GCFG_INVERT: i32 = 0x3007;
+pub(crate) const REQ_SCFG_AXISMAP: i32 = 0x3008;
+pub(crate) const REQ_GCFG_AXISMAP: i32 = 0x3009;
+pub(crate) const REQ_SCFG_SWAPYZ: i32 = 0x3010;
+pub(crate) const REQ_GCFG_SWAPYZ: i32 = 0x3011;
+pub(crate) const REQ_CFG_SAVE: i32 = 0x3ffe;
+
+// Strings travel 24 bytes per packet in data[1..7]. data[7] holds how many
+// bytes are left including this packet, with STR_CONT set after the first.
+pub(crate) const STR_CHUNK: usize = 24;
+pub(crate) const STR_CONT: i32 = 0x10000;
+
+pub(crate) fn is_response(packet: &Packet) -> bool {
+    packet[0] & REQ_TAG_MASK == REQ_TAG
+}
+
+pub(crate) fn request_type(packet: &Packet) -> i32 {
+    packet[0] & !REQ_TAG_MASK
+}
+
+pub(crate) fn request(req: i32, data: [i32; 7]) -> Packet {
+    let mut packet = [REQ_TAG | req, 0, 0, 0, 0, 0, 0, 0];
+    packet[1..].copy_from_slice(&data);
+    packet
+}
+
+pub(crate) fn encode_string(req: i32, s: &[u8]) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    let mut rest = s;
+    loop {
+        let (chunk, tail) = rest.split_at(rest.len().min(STR_CHUNK));
+        let mut bytes = [0u8; STR_CHUNK];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        let mut data = [0; 7];
+        data[..6].copy_from_slice(&parse(&bytes)[..6]);
+        data[6] = rest.len() as i32 | if packets.is_empty() { 0 } else { STR_CONT };
+        packets.push(request(req, data));
+        if tail.is_empty() {
+            return packets;
+        }
+        rest = tail;
+    }
+}
+
+// Appends the string bytes carried by `packet`, returning whether more follow
+pub(crate) fn decode_string(packet: &Packet, out: &mut Vec<u8>) -> bool {
+    let remaining = (packet[7] & (STR_CONT - 1)) as usize;
+    out.extend_from_slice(&to_bytes(packet)[4..4 + remaining.min(STR_CHUNK)]);
+    remaining > STR_CHUNK
+}
+
+pub(crate) fn parse(bytes: &[u8]) -> Packet {
+    let mut packet = [0; 8];
+    for (v, chunk) in packet.iter_mut().zip(bytes.chunks_exact(4)) {
+        *v = i32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    packet
+}
+
+pub(crate) fn to_bytes(packet: &Packet) -> [u8; PACKET_SIZE] {
+    let mut bytes = [0; PACKET_SIZE];
+    for (chunk, v) in bytes.chunks_exact_mut(4).zip(packet) {
+        chunk.copy_from_slice(&v.to_ne_bytes());
+    }
+    bytes
+}
+
+pub(crate) fn encode(event: &Event) -> Packet {
+    match event {
+        Event::Motion(m) => [UEV_MOTION, m.x, m.y, m.z, m.rx, m.ry, m.rz, m.period as i32],
+        Event::Button(b) => {
+            let type_ = if b.press { UEV_PRESS } else { UEV_RELEASE };
+            [type_, b.bnum, 0, 0, 0, 0, 0, 0]
+        }
+        Event::Device(d) => [
+            UEV_DEV,
+            if d.added { DEV_ADD } else { DEV_ADD + 1 },
+            d.id,
+            d.devtype,
+            d.vendor_id as i32,
+            d.product_id as i32,
+            0,
+            0,
+        ],
+        Event::Config(c) => {
+            let d = c.data;
+            [UEV_CFG, c.cfg, d[0], d[1], d[2], d[3], d[4], d[5]]
+        }
+        Event::RawAxis(a) => [UEV_RAWAXIS, a.idx, a.value, 0, 0, 0, 0, 0],
+        Event::RawButton(b) => [UEV_RAWBUTTON, b.bnum, b.press as i32, 0, 0, 0, 0, 0],
+    }
+}
+
+pub(crate) fn decode(packet: &Packet) -> Result<Event> {
+    match packet[0] {
+        UEV_MOTION => Ok(Event::Motion(MotionEvent {
+            x: packet[1],
+            y: packet[2],
+            z: packet[3],
+            rx: packet[4],
+            ry: packet[5],
+            rz: packet[6],
+            period: packet[7] as u32,
+        })),
+        UEV_PRESS | UEV_RELEASE => Ok(Event::Button(ButtonEvent {
+            press: packet[0] == UEV_PRESS,
+            bnum: packet[1],
+        })),
+        UEV_DEV => Ok(Event::Device(DeviceEvent {
+            added: packet[1] == DEV_ADD,
+            id: packet[2],
+            devtype: packet[3],
+            vendor_id: packet[4] as u16,
+            product_id: packet[5] as u16,
+        })),
+        UEV_CFG => {
+            let mut data = [0; 6];
+            data.copy_from_slice(&packet[2..]);
+            Ok(Event::Config(ConfigEvent {
+                cfg: packet[1],
+                data,
+            }))
+        }
+        UEV_RAWAXIS => Ok(Event::RawAxis(RawAxisEvent {
+            idx: packet[1],
+            value: packet[2],
+        })),
+        UEV_RAWBUTTON => Ok(Event::RawButton(ButtonEvent {
+            press: packet[2] != 0,
+            bnum: packet[1],
+        })),
+        type_ => Err(Error::UnknownEvent { type_ }),
+    }
+}
+
+pub(crate) fn evmask(types: &[EventType]) -> i32 {
+    types.iter().fold(0, |mask, t| {
+        mask | match t {
+            EventType::Any => EVMASK_ALL,
+            EventType::Motion => EVMASK_MOTION,
+            EventType::Button => EVMASK_BUTTON,
+            EventType::Device => EVMASK_DEV,
+            EventType::Config => EVMASK_CFG,
+            EventType::RawAxis => EVMASK_RAWAXIS,
+            EventType::RawButton => EVMASK_RAWBUTTON,
+        }
+    })
+}
+
+// Whether an event packet gets through `mask`. Unknown types always do, so
+// they still surface as Error::UnknownEvent.
+pub(crate) fn in_mask(packet: &Packet, mask: i32) -> bool {
+    let bit = match packet[0] {
+        UEV_MOTION => EVMASK_MOTION,
+        UEV_PRESS | UEV_RELEASE => EVMASK_BUTTON,
+        UEV_DEV => EVMASK_DEV,
+        UEV_CFG => EVMASK_CFG,
+        UEV_RAWAXIS => EVMASK_RAWAXIS,
+        UEV_RAWBUTTON => EVMASK_RAWBUTTON,
+        _ => return true,
+    };
+    mask & bit != 0
+}
+
+// Whether a raw packet would be decoded as an event of type `t`
+pub(crate) fn matches(packet: &Packet, t: EventType) -> bool {
+    match t {
+        EventType::Any => true,
+        EventType::Motion => packet[0] == UEV_MOTION,
+        EventType::Button => packet[0] == UEV_PRESS || packet[0] == UEV_RELEASE,
+        EventType::Device => packet[0] == UEV_DEV,
+        EventType::Config => packet[0] == UEV_CFG,
+        EventType::RawAxis => packet[0] == UEV_RAWAXIS,
+        EventType::RawButton => packet[0] == UEV_RAWBUTTON,
+    }
+}
@@ -0,0 +1,89 @@
+// Connection::spawn_reader: a thread that waits on a Connection and sends what
+// it reads down a channel, for applications that would rather not write that
+// loop themselves. The channel is std's mpsc, or crossbeam's with the
+// `crossbeam` feature.
+use super::*;
+use std::thread::{self, JoinHandle};
+
+#[cfg(feature = "crossbeam")]
+pub use crossbeam_channel::Receiver;
+#[cfg(not(feature = "crossbeam"))]
+pub use std::sync::mpsc::Receiver;
+
+// Events as read, and read errors as values. Error::UnknownEvent is passed on
+// and the thread carries on; any other error is the last thing sent.
+pub type EventReceiver = Receiver<Result<Event>>;
+
+// Stops the reader thread when dropped, unless it has been joined already
+#[derive(Debug)]
+pub struct ReaderHandle {
+    cancel: CancelHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+pub(crate) fn spawn(conn: Connection) -> Result<(EventReceiver, ReaderHandle)> {
+    #[cfg(feature = "crossbeam")]
+    let (tx, rx) = crossbeam_channel::unbounded();
+    #[cfg(not(feature = "crossbeam"))]
+    let (tx, rx) = std::sync::mpsc::channel();
+    let cancel = CancelHandle::new()?;
+    let thread = {
+        let cancel = cancel.clone();
+        thread::Builder::new()
+            .name("spacenav-reader".into())
+            .spawn(move || loop {
+                let (result, last) = match conn.wait_cancellable(&cancel) {
+                    Ok(WaitOutcome::Event(event)) => (Ok(event), false),
+                    // Stopped through the handle; dropping tx tells the receiver
+                    Ok(_) => return,
+                    Err(e @ Error::UnknownEvent { .. }) => (Err(e), false),
+                    Err(e) => (Err(e), true),
+                };
+                // Also stops once nobody is listening any more, which a
+                // channel only reports on sending
+                if tx.send(result).is_err() || last {
+                    return;
+                }
+            })
+            .map_err(|e| Error::from_io(Operation::Open, &e))?
+    };
+    Ok((
+        rx,
+        ReaderHandle {
+            cancel,
+            thread: Some(thread),
+        },
+    ))
+}
+
+impl ReaderHandle {
+    // Has the thread finish, closing the connection and the channel once
+    // whatever it already sent has been received
+    pub fn stop(mut self) {
+        self.cancel.cancel();
+        self.join_thread();
+    }
+    // Waits for the thread to end by itself, i.e. for the connection to fail.
+    // A dropped receiver is only noticed when the next event arrives, so with
+    // the device idle this can block indefinitely; use `stop` to end it.
+    pub fn join(mut self) {
+        self.join_thread();
+    }
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    fn join_thread(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            // The loop doesn't panic; nothing to report if it somehow did
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ReaderHandle {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+        self.join_thread();
+    }
+}
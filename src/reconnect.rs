@@ -0,0 +1,137 @@
+// A Connection that survives spacenavd restarting. When the daemon hangs up it
+// reports ConnectionEvent::Disconnected, then keeps reconnecting with
+// exponential backoff and reports ConnectionEvent::Reconnected once it is
+// back. The dead Connection is dropped before a new one is opened, so with
+// libspnav the global connection gets closed and reopened in between, as long
+// as no other handle is still holding on to it.
+use super::*;
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    Event(Event),
+    // The daemon went away. Anything derived from its events, e.g. held
+    // buttons, is stale.
+    Disconnected,
+    // A new connection is up, with the builder's options applied again
+    Reconnected,
+}
+
+#[derive(Debug)]
+pub struct ReconnectingConnection {
+    builder: ConnectionBuilder,
+    conn: Option<Connection>,
+    ever_connected: bool,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    delay: Duration,
+    // None once the backoff is too long to schedule, i.e. never
+    next_attempt: Option<Instant>,
+}
+
+impl ReconnectingConnection {
+    // Tries to connect right away. Only errors no retry can fix, like
+    // Error::PermissionDenied, are returned; otherwise the first connection is
+    // made later, without a Reconnected event.
+    pub(crate) fn new(builder: ConnectionBuilder) -> Result<ReconnectingConnection> {
+        let mut conn = ReconnectingConnection {
+            builder,
+            conn: None,
+            ever_connected: false,
+            min_backoff: MIN_BACKOFF,
+            max_backoff: MAX_BACKOFF,
+            delay: MIN_BACKOFF,
+            next_attempt: Some(Instant::now()),
+        };
+        conn.reconnect()?;
+        Ok(conn)
+    }
+    // How long to wait after the first failed attempt, doubling each time up to
+    // `max`. Defaults to 100ms and 5s.
+    pub fn backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.min_backoff = min;
+        self.max_backoff = max.max(min);
+        self.delay = min;
+        self
+    }
+    // The live connection, if there is one right now
+    pub fn connection(&self) -> Option<&Connection> {
+        self.conn.as_ref()
+    }
+    pub fn is_connected(&self) -> bool {
+        self.conn.is_some()
+    }
+    // Returns the next pending event without blocking. While disconnected this
+    // makes a reconnection attempt whenever the backoff allows one.
+    pub fn poll(&mut self) -> Result<Option<ConnectionEvent>> {
+        if self.conn.is_none() {
+            if self.next_attempt.is_none_or(|at| Instant::now() < at) {
+                return Ok(None);
+            }
+            if let Some(event) = self.reconnect()? {
+                return Ok(Some(event));
+            }
+        }
+        match &self.conn {
+            Some(conn) => {
+                let result = conn.try_poll();
+                self.handle(result)
+            }
+            None => Ok(None),
+        }
+    }
+    // Blocks until an event arrives or the connection is lost or regained
+    pub fn wait(&mut self) -> Result<ConnectionEvent> {
+        loop {
+            let Some(conn) = &self.conn else {
+                let left = self.next_attempt.map_or(Duration::MAX, |at| {
+                    at.saturating_duration_since(Instant::now())
+                });
+                std::thread::sleep(left);
+                if let Some(event) = self.reconnect()? {
+                    return Ok(event);
+                }
+                continue;
+            };
+            let result = conn.wait().map(Some);
+            if let Some(event) = self.handle(result)? {
+                return Ok(event);
+            }
+        }
+    }
+
+    // Makes one connection attempt, backing off further if it fails
+    fn reconnect(&mut self) -> Result<Option<ConnectionEvent>> {
+        match self.builder.clone().connect() {
+            Ok(conn) => {
+                self.conn = Some(conn);
+                self.delay = self.min_backoff;
+                let was_connected = std::mem::replace(&mut self.ever_connected, true);
+                Ok(was_connected.then_some(ConnectionEvent::Reconnected))
+            }
+            Err(e) if e.is_retryable() => {
+                self.next_attempt = Instant::now().checked_add(self.delay);
+                self.delay = self
+                    .delay
+                    .checked_mul(2)
+                    .map_or(self.max_backoff, |d| d.min(self.max_backoff));
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+    fn handle(&mut self, result: Result<Option<Event>>) -> Result<Option<ConnectionEvent>> {
+        match result {
+            Ok(event) => Ok(event.map(ConnectionEvent::Event)),
+            Err(e) if e.is_retryable() => {
+                // Dropped here, before anything reconnects
+                self.conn = None;
+                self.next_attempt = Some(Instant::now());
+                Ok(Some(ConnectionEvent::Disconnected))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+// Frame-sampled device state, for game loops that want to know where the axes
+// are and which buttons are held rather than walk an event queue. Feed every
+// event to `update` as it arrives, then take a `snapshot` once per frame.
+use super::*;
+use std::collections::BTreeSet;
+
+const DEFAULT_MOTION_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone)]
+pub struct DeviceState {
+    // The latest motion and when it arrived
+    motion: Option<(MotionEvent, Instant)>,
+    motion_timeout: Duration,
+    held: BTreeSet<i32>,
+    pressed: BTreeSet<i32>,
+    released: BTreeSet<i32>,
+}
+
+// The state at one point in time. `pressed` and `released` cover everything
+// since the previous snapshot, so a button tapped within one frame shows up
+// in both even though it isn't held.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateSnapshot {
+    pub motion: MotionEvent,
+    pub held: Vec<i32>,
+    pub pressed: Vec<i32>,
+    pub released: Vec<i32>,
+}
+
+impl StateSnapshot {
+    pub fn is_held(&self, bnum: i32) -> bool {
+        self.held.contains(&bnum)
+    }
+    pub fn was_pressed(&self, bnum: i32) -> bool {
+        self.pressed.contains(&bnum)
+    }
+    pub fn was_released(&self, bnum: i32) -> bool {
+        self.released.contains(&bnum)
+    }
+}
+
+impl Default for DeviceState {
+    fn default() -> Self {
+        DeviceState::new()
+    }
+}
+
+impl DeviceState {
+    // Motion older than 200ms reads as zero unless changed with
+    // `motion_timeout`
+    pub fn new() -> DeviceState {
+        DeviceState {
+            motion: None,
+            motion_timeout: DEFAULT_MOTION_TIMEOUT,
+            held: BTreeSet::new(),
+            pressed: BTreeSet::new(),
+            released: BTreeSet::new(),
+        }
+    }
+    // How long the latest motion counts without a new one. The daemon keeps
+    // sending motion while the cap is off center, so going quiet means it was
+    // let go and the zero event got lost, or the connection did.
+    pub fn motion_timeout(mut self, timeout: Duration) -> Self {
+        self.motion_timeout = timeout;
+        self
+    }
+    pub fn update(&mut self, event: &Event) {
+        self.update_at(event, Instant::now());
+    }
+    // Like `update`, for an event that arrived at `now`
+    pub fn update_at(&mut self, event: &Event, now: Instant) {
+        match event {
+            Event::Motion(motion) => self.motion = Some((motion.clone(), now)),
+            Event::Button(ButtonEvent { press: true, bnum }) => {
+                self.held.insert(*bnum);
+                self.pressed.insert(*bnum);
+            }
+            Event::Button(ButtonEvent { press: false, bnum }) => {
+                self.held.remove(bnum);
+                self.released.insert(*bnum);
+            }
+            _ => {}
+        }
+    }
+    // The latest motion, or all zeros once it has timed out
+    pub fn motion(&self) -> MotionEvent {
+        self.motion_at(Instant::now())
+    }
+    pub fn motion_at(&self, now: Instant) -> MotionEvent {
+        match &self.motion {
+            Some((motion, at)) if now.saturating_duration_since(*at) <= self.motion_timeout => {
+                motion.clone()
+            }
+            _ => MotionEvent::default(),
+        }
+    }
+    pub fn is_held(&self, bnum: i32) -> bool {
+        self.held.contains(&bnum)
+    }
+    // Reads the current state and starts collecting presses and releases for
+    // the next snapshot
+    pub fn snapshot(&mut self) -> StateSnapshot {
+        self.snapshot_at(Instant::now())
+    }
+    pub fn snapshot_at(&mut self, now: Instant) -> StateSnapshot {
+        StateSnapshot {
+            motion: self.motion_at(now),
+            held: self.held.iter().copied().collect(),
+            pressed: std::mem::take(&mut self.pressed).into_iter().collect(),
+            released: std::mem::take(&mut self.released).into_iter().collect(),
+        }
+    }
+    // Forgets everything, e.g. after ConnectionEvent::Disconnected when the
+    // held buttons can no longer be trusted
+    pub fn clear(&mut self) {
+        self.motion = None;
+        self.held.clear();
+        self.pressed.clear();
+        self.released.clear();
+    }
+}
@@ -0,0 +1,168 @@
+// Thin wrappers over the libc calls std doesn't expose.
+use std::io;
+#[cfg(feature = "native")]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(feature = "native")]
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::RawFd;
+#[cfg(feature = "native")]
+use std::os::unix::net::UnixStream;
+#[cfg(feature = "native")]
+use std::path::Path;
+#[cfg(feature = "native")]
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Readiness {
+    Readable,
+    // The peer hung up. Buffered data may still be readable.
+    HungUp,
+    // The wake fd became readable first
+    Woken,
+    TimedOut,
+}
+
+// Blocks until `fd` or `wake` is readable or `deadline` passes; None waits
+// forever. Interrupted polls are retried.
+pub(crate) fn poll_readable(
+    fd: RawFd,
+    wake: Option<RawFd>,
+    deadline: Option<Instant>,
+) -> io::Result<Readiness> {
+    let pollfd = |fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let mut fds = [pollfd(fd), pollfd(wake.unwrap_or(-1))];
+    let nfds = if wake.is_some() { 2 } else { 1 };
+    loop {
+        match unsafe { libc::poll(fds.as_mut_ptr(), nfds, timeout_ms(deadline)) } {
+            0 => return Ok(Readiness::TimedOut),
+            n if n > 0 => {
+                return Ok(if fds[1].revents != 0 {
+                    Readiness::Woken
+                } else if fds[0].revents & (libc::POLLHUP | libc::POLLERR) != 0 {
+                    Readiness::HungUp
+                } else {
+                    Readiness::Readable
+                })
+            }
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+// Blocks until `fd` can be written to; false if `deadline` passed first
+#[cfg(feature = "native")]
+pub(crate) fn poll_writable(fd: RawFd, deadline: Option<Instant>) -> io::Result<bool> {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLOUT,
+        revents: 0,
+    };
+    loop {
+        match unsafe { libc::poll(&mut pfd, 1, timeout_ms(deadline)) } {
+            0 => return Ok(false),
+            n if n > 0 => return Ok(true),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+// Connects without blocking past `deadline`, returning a nonblocking stream.
+// A listener with a full backlog is retried until then.
+#[cfg(feature = "native")]
+pub(crate) fn connect_unix(path: &Path, deadline: Option<Instant>) -> io::Result<UnixStream> {
+    let bytes = path.as_os_str().as_bytes();
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    if bytes.len() >= addr.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "socket path too long",
+        ));
+    }
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (dst, src) in addr.sun_path.iter_mut().zip(bytes) {
+        *dst = *src as libc::c_char;
+    }
+    let flags = libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+    let fd = unsafe { libc::socket(libc::AF_UNIX, flags, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Owns the fd from here on, so it is closed on every error path
+    let stream = unsafe { UnixStream::from_raw_fd(fd) };
+    let timed_out = || io::Error::from_raw_os_error(libc::ETIMEDOUT);
+    loop {
+        let addr_ptr = &addr as *const libc::sockaddr_un as *const libc::sockaddr;
+        let len = std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+        if unsafe { libc::connect(fd, addr_ptr, len) } == 0 {
+            return Ok(stream);
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EINTR) => {}
+            Some(libc::EINPROGRESS) => {
+                if !poll_writable(fd, deadline)? {
+                    return Err(timed_out());
+                }
+                return match stream.take_error()? {
+                    Some(err) => Err(err),
+                    None => Ok(stream),
+                };
+            }
+            Some(libc::EAGAIN) => {
+                let left = deadline.map_or(Duration::MAX, |d| {
+                    d.saturating_duration_since(Instant::now())
+                });
+                if left.is_zero() {
+                    return Err(timed_out());
+                }
+                std::thread::sleep(left.min(Duration::from_millis(10)));
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+fn timeout_ms(deadline: Option<Instant>) -> i32 {
+    match deadline {
+        None => -1,
+        Some(deadline) => {
+            let left = deadline.saturating_duration_since(Instant::now());
+            // Round up so we never wake just before the deadline and spin
+            let ms = left.as_nanos().div_ceil(1_000_000);
+            ms.min(i32::MAX as u128) as i32
+        }
+    }
+}
+
+// Zeroes errno, so a failure that doesn't set it isn't blamed on whatever
+// failed earlier on this thread
+#[cfg(feature = "libspnav")]
+pub(crate) fn clear_errno() {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    unsafe {
+        *libc::__errno_location() = 0;
+    }
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+    unsafe {
+        *libc::__error() = 0;
+    }
+    #[cfg(any(target_os = "openbsd", target_os = "netbsd"))]
+    unsafe {
+        *libc::__errno() = 0;
+    }
+}
@@ -0,0 +1,285 @@
+// The Magellan X11 protocol spoken by the 3Dconnexion driver, and by
+// spacenavd when running under X. Applications register a window with the
+// driver, which then sends it ClientMessages carrying motion and button
+// events. These are what libspnav's spnav_x11_* functions did.
+//
+// `Client` owns an X connection and a hidden window of its own. Applications
+// that already run an x11rb event loop can instead register their own window
+// with `register_window` and pass every event they receive through `decode`.
+use super::*;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicI32, Ordering};
+use x11rb::connection::Connection as _;
+use x11rb::errors::{ConnectError, ConnectionError, ReplyOrIdError};
+use x11rb::protocol::xproto::{
+    self, AtomEnum, ClientMessageEvent, ConnectionExt as _, CreateWindowAux, EventMask, Window,
+    WindowClass,
+};
+use x11rb::rust_connection::RustConnection;
+
+// data.s[2] of the CommandEvent that tells the driver where to send events
+pub(crate) const CMD_APP_WINDOW: u16 = 27695;
+// WM_NAME of the driver's window
+pub(crate) const DRIVER_WINDOW_NAME: &[u8] = b"Magellan Window";
+
+// The message types of Magellan ClientMessages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Atoms {
+    pub motion: xproto::Atom,
+    pub button_press: xproto::Atom,
+    pub button_release: xproto::Atom,
+    pub command: xproto::Atom,
+}
+
+impl Atoms {
+    pub fn intern<C: x11rb::connection::Connection>(conn: &C) -> Result<Atoms> {
+        let op = Operation::Open;
+        let cookies = [
+            conn.intern_atom(false, b"MotionEvent"),
+            conn.intern_atom(false, b"ButtonPressEvent"),
+            conn.intern_atom(false, b"ButtonReleaseEvent"),
+            conn.intern_atom(false, b"CommandEvent"),
+        ];
+        let mut atoms = [0; 4];
+        for (atom, cookie) in atoms.iter_mut().zip(cookies) {
+            let cookie = cookie.map_err(|e| connection_error(op, e))?;
+            *atom = cookie.reply().map_err(|e| reply_error(op, e.into()))?.atom;
+        }
+        Ok(Atoms {
+            motion: atoms[0],
+            button_press: atoms[1],
+            button_release: atoms[2],
+            command: atoms[3],
+        })
+    }
+}
+
+// The driver's window, which the root window's CommandEvent property points
+// at; None when no driver is running
+pub fn driver_window<C: x11rb::connection::Connection>(
+    conn: &C,
+    atoms: &Atoms,
+    root: Window,
+) -> Result<Option<Window>> {
+    let op = Operation::Open;
+    let property = |window, name, type_: AtomEnum, len| -> Result<_> {
+        conn.get_property(false, window, name, type_, 0, len)
+            .map_err(|e| connection_error(op, e))?
+            .reply()
+            .map_err(|e| reply_error(op, e.into()))
+    };
+    let reply = property(root, atoms.command, AtomEnum::ANY, 1)?;
+    let Some(window) = reply.value32().and_then(|mut values| values.next()) else {
+        return Ok(None);
+    };
+    // A stale property left behind by a driver that has since exited
+    let name = match property(window, AtomEnum::WM_NAME.into(), AtomEnum::STRING, 16) {
+        Ok(name) => name.value,
+        Err(Error::Rejected { .. }) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok((name == DRIVER_WINDOW_NAME).then_some(window))
+}
+
+// Has the driver send events to `window`, like spnav_x11_window. Registering
+// the root window stops them.
+pub fn register_window<C: x11rb::connection::Connection>(
+    conn: &C,
+    atoms: &Atoms,
+    root: Window,
+    window: Window,
+) -> Result<()> {
+    let op = Operation::Open;
+    let Some(driver) = driver_window(conn, atoms, root)? else {
+        return Err(Error::NotRunning { op, errno: 0 });
+    };
+    let data: [u16; 10] = [
+        (window >> 16) as u16,
+        window as u16,
+        CMD_APP_WINDOW,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+    ];
+    let message = ClientMessageEvent::new(16, window, atoms.command, data);
+    conn.send_event(false, driver, EventMask::NO_EVENT, message)
+        .map_err(|e| connection_error(op, e))?;
+    conn.flush().map_err(|e| connection_error(op, e))
+}
+
+// Turns a Magellan ClientMessage into an Event, like spnav_x11_event. Anything
+// else gives None.
+pub fn decode(atoms: &Atoms, event: &x11rb::protocol::Event) -> Option<Event> {
+    let x11rb::protocol::Event::ClientMessage(message) = event else {
+        return None;
+    };
+    // Shorts on the wire are signed
+    let s = message.data.as_data16().map(|v| v as i16 as i32);
+    if message.type_ == atoms.motion {
+        Some(Event::Motion(MotionEvent {
+            x: s[2],
+            y: s[3],
+            z: s[4],
+            rx: s[5],
+            ry: s[6],
+            rz: s[7],
+            period: s[8] as u32,
+        }))
+    } else if message.type_ == atoms.button_press || message.type_ == atoms.button_release {
+        Some(Event::Button(ButtonEvent {
+            press: message.type_ == atoms.button_press,
+            bnum: s[2],
+        }))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct Client {
+    conn: RustConnection,
+    atoms: Atoms,
+    root: Window,
+    // The hidden window created for us
+    own_window: Window,
+    mask: AtomicI32,
+}
+
+impl Client {
+    // Connects to $DISPLAY
+    pub fn open() -> Result<Client> {
+        Client::connect(None)
+    }
+    pub fn connect(display: Option<&str>) -> Result<Client> {
+        let op = Operation::Open;
+        let (conn, screen) = x11rb::connect(display).map_err(connect_error)?;
+        let root = conn.setup().roots[screen].root;
+        let atoms = Atoms::intern(&conn)?;
+        let own_window = conn.generate_id().map_err(|e| reply_error(op, e))?;
+        conn.create_window(
+            0,
+            own_window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )
+        .map_err(|e| connection_error(op, e))?;
+        let client = Client {
+            conn,
+            atoms,
+            root,
+            own_window,
+            mask: AtomicI32::new(proto::EVMASK_ALL),
+        };
+        register_window(&client.conn, &client.atoms, root, own_window)?;
+        Ok(client)
+    }
+    pub fn fd(&self) -> RawFd {
+        self.conn.stream().as_raw_fd()
+    }
+    pub fn atoms(&self) -> &Atoms {
+        &self.atoms
+    }
+    // Moves events over to one of the application's own windows, which the
+    // driver may want e.g. to only send events while it has focus. They then
+    // arrive through the application's event loop, for `decode`.
+    pub fn set_window(&self, window: Window) -> Result<()> {
+        register_window(&self.conn, &self.atoms, self.root, window)
+    }
+    // The driver only sends motion and button events, so this is all there is
+    // to filter
+    pub fn set_event_mask(&self, types: &[EventType]) {
+        self.mask.store(proto::evmask(types), Ordering::Relaxed);
+    }
+    // Returns the next pending event without blocking
+    pub fn poll(&self) -> Result<Option<Event>> {
+        let op = Operation::PollEvent;
+        while let Some(event) = self
+            .conn
+            .poll_for_event()
+            .map_err(|e| connection_error(op, e))?
+        {
+            if let Some(event) = self.accept(&event) {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+    // Blocks until the driver sends an event
+    pub fn wait(&self) -> Result<Event> {
+        let op = Operation::WaitEvent;
+        loop {
+            let event = self
+                .conn
+                .wait_for_event()
+                .map_err(|e| connection_error(op, e))?;
+            if let Some(event) = self.accept(&event) {
+                return Ok(event);
+            }
+        }
+    }
+
+    fn accept(&self, event: &x11rb::protocol::Event) -> Option<Event> {
+        let event = decode(&self.atoms, event)?;
+        let mask = self.mask.load(Ordering::Relaxed);
+        (proto::evmask(&[event.event_type()]) & mask != 0).then_some(event)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // Like spnav_close: point the driver back at the root window. Errors
+        // are ignored, the X server may already be gone.
+        let _ = register_window(&self.conn, &self.atoms, self.root, self.root);
+        let _ = self.conn.destroy_window(self.own_window);
+        let _ = self.conn.flush();
+    }
+}
+
+pub(crate) fn connect_error(e: ConnectError) -> Error {
+    let op = Operation::Open;
+    match e {
+        ConnectError::IoError(e) => Error::from_io(op, &e),
+        ConnectError::SetupAuthenticate(_) | ConnectError::SetupFailed(_) => {
+            Error::PermissionDenied {
+                op,
+                errno: libc::EACCES,
+            }
+        }
+        _ => Error::NotRunning { op, errno: 0 },
+    }
+}
+
+pub(crate) fn connection_error(op: Operation, e: ConnectionError) -> Error {
+    match e {
+        ConnectionError::IoError(e) => Error::from_io(op, &e),
+        _ => Error::Os {
+            op,
+            errno: libc::EPROTO,
+        },
+    }
+}
+
+pub(crate) fn reply_error(op: Operation, e: ReplyOrIdError) -> Error {
+    match e {
+        ReplyOrIdError::ConnectionError(e) => connection_error(op, e),
+        ReplyOrIdError::X11Error(e) => Error::Rejected {
+            op,
+            status: e.error_code as i32,
+        },
+        ReplyOrIdError::IdsExhausted => Error::Os {
+            op,
+            errno: libc::ENOMEM,
+        },
+    }
+}
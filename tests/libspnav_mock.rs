@@ -0,0 +1,40 @@
+// libspnav against the mock daemon, in a process of its own: libspnav keeps a
+// single global connection and finds the daemon through the environment,
+// which has to be set up before any thread exists to read it. Hence no test
+// harness, just main.
+use spacenav_plus::mock::MockDaemon;
+use spacenav_plus::{lib, ButtonEvent, Event, EventType, MotionEvent};
+use std::time::Duration;
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("spacenav-libspnav-{}", std::process::id()));
+    // libspnav 1.x looks at SPNAV_SOCKET first, then $XDG_RUNTIME_DIR/spnav.sock
+    std::env::set_var("SPNAV_SOCKET", dir.join("spnav.sock"));
+    std::env::set_var("XDG_RUNTIME_DIR", &dir);
+    let daemon = MockDaemon::start_in(&dir).expect("mock daemon");
+
+    let motion = Event::Motion(MotionEvent {
+        x: 1,
+        y: -2,
+        z: 3,
+        rx: -4,
+        ry: 5,
+        rz: -6,
+        period: 16,
+    });
+    let button = Event::Button(ButtonEvent {
+        press: true,
+        bnum: 1,
+    });
+    lib::spnav_open().expect("spnav_open against the mock daemon");
+    assert!(daemon.wait_for_clients(1, Duration::from_secs(2)));
+    daemon.send(&motion);
+    daemon.send(&button);
+    daemon.send(&motion);
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(lib::spnav_remove_events(EventType::Motion), 2);
+    assert_eq!(lib::spnav_poll_event(), Some(button));
+    assert_eq!(lib::spnav_poll_event(), None);
+    lib::spnav_close().expect("spnav_close");
+    println!("libspnav against the mock daemon: ok");
+}